@@ -0,0 +1,348 @@
+//! read the AppStream catalogs of the flatpak remotes
+// appstream.rs
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use chrono::DateTime;
+use flate2::read::GzDecoder;
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+
+use crate::error::PacpakError;
+use crate::flatpak::{default_arch, installation_path};
+
+/// file names of the catalogs (in `installation/appstream/remote/arch/active/`)
+mod text {
+	pub const CATALOG: &str = "appstream.xml";
+	pub const CATALOG_GZ: &str = "appstream.xml.gz";
+}
+
+/// an app (or runtime) in an AppStream catalog
+#[derive(Debug, Default, Clone)]
+pub struct Component {
+	/// appID (in reverse dns form)
+	pub id: String,
+	pub name: String,
+	pub summary: String,
+	/// the flatpak ref (eg. `app/org.gimp.GIMP/x86_64/stable`)
+	pub flatpak_ref: String,
+	/// the remote of the catalog
+	pub remote: String,
+	/// binaries the app provides (eg. `gimp`)
+	pub binaries: Vec<String>,
+	/// desktop files of the app (eg. `org.gimp.GIMP.desktop`)
+	pub launchables: Vec<String>,
+	/// version of the newest release
+	pub version: String,
+	pub keywords: Vec<String>,
+	/// eg. `Graphics`
+	pub categories: Vec<String>,
+	pub developer: String,
+	/// the releases (newest first)
+	pub releases: Vec<Release>,
+}
+
+/// a release of a component (from `<releases>`)
+#[derive(Debug, Default, Clone)]
+pub struct Release {
+	pub version: String,
+	/// eg. `2024-06-10`
+	pub date: String,
+	/// the paragraphs and list items of the release notes (list items start with `- `)
+	pub notes: Vec<String>,
+}
+
+impl Release {
+	/// read a release from its element (eg. `<release version="1.0" date="2024-06-10">`)
+	fn from_element(element: &BytesStart) -> Self {
+		// the date can also be given as a unix timestamp
+		let date = attribute(element, "date")
+			.or_else(|| attribute(element, "timestamp")
+				.and_then(|t| t.parse::<i64>().ok())
+				.and_then(|t| DateTime::from_timestamp(t, 0))
+				.map(|time| time.format("%Y-%m-%d").to_string()))
+			.unwrap_or_default();
+		Self {
+			version: attribute(element, "version").unwrap_or_default(),
+			date: date.split('T').next().unwrap_or_default().to_string(),
+			notes: Vec::new(),
+		}
+	}
+}
+
+/// find the AppStream catalogs of all remotes (in the system and user installation)
+/// returns tuples of (remote, path)
+pub fn catalog_paths() -> Vec<(String, PathBuf)> {
+	let arch = default_arch();
+	let mut out = Vec::new();
+	for installation in ["system", "user"].iter().filter_map(|name| installation_path(name)) {
+		let Ok(remotes) = fs::read_dir(installation.join("appstream")) else {
+			continue;
+		};
+		for remote in remotes.flatten() {
+			let dir = remote.path().join(&arch).join("active");
+			let path = [text::CATALOG, text::CATALOG_GZ].iter()
+				.map(|name| dir.join(name))
+				.find(|path| path.exists());
+			if let Some(path) = path {
+				out.push((remote.file_name().to_string_lossy().to_string(), path));
+			}
+		}
+	}
+	out
+}
+
+/// the components of the catalogs (each remote is only read once)
+#[derive(Debug, Default)]
+pub struct Catalogs {
+	remotes: HashMap<String, Vec<Component>>,
+}
+
+impl Catalogs {
+	/// find a component in the catalogs of a remote (by its id)
+	/// returns None if no catalog of the remote contains it
+	pub fn find_component(&mut self, remote: &str, id: &str) -> Result<Option<&Component>, PacpakError> {
+		if !self.remotes.contains_key(remote) {
+			let mut components = Vec::new();
+			for (_, path) in catalog_paths().into_iter().filter(|(name, _)| name == remote) {
+				components.extend(read_catalog(remote, &path)?);
+			}
+			self.remotes.insert(remote.to_string(), components);
+		}
+		Ok(self.remotes[remote].iter().find(|c| c.id == id))
+	}
+}
+
+/// read all components of a catalog (`appstream.xml` or `appstream.xml.gz`)
+pub fn read_catalog(remote: &str, path: &Path) -> Result<Vec<Component>, PacpakError> {
+	let file = File::open(path)?;
+	let input: Box<dyn BufRead> = if path.extension().is_some_and(|e| e == "gz") {
+		Box::new(BufReader::new(GzDecoder::new(file)))
+	} else {
+		Box::new(BufReader::new(file))
+	};
+	parse_catalog(remote, input)
+		.map_err(|e| PacpakError::Parse(format!("{} ({})", path.display(), e)))
+}
+
+/// parse the xml of a catalog
+fn parse_catalog(remote: &str, input: Box<dyn BufRead>) -> Result<Vec<Component>, quick_xml::Error> {
+	let mut reader = Reader::from_reader(input);
+	reader.config_mut().trim_text(true);
+
+	let mut out = Vec::new();
+	let mut component: Option<Component> = None;
+	// names of the open elements (inside the current component)
+	// and if they are translated (have a `xml:lang` attribute or a translated parent)
+	let mut stack: Vec<(String, bool)> = Vec::new();
+	let mut launchable = false;
+	// the depth of the release note that is read (its text can contain markup, eg. `<p>a <em>b</em></p>`)
+	let mut note_depth: Option<usize> = None;
+	let mut buf = Vec::new();
+	loop {
+		match reader.read_event_into(&mut buf)? {
+			Event::Start(e) => {
+				let name = String::from_utf8_lossy(e.name().as_ref()).to_string();
+				if name == "component" {
+					component = Some(Component { remote: remote.to_string(), ..Default::default() });
+					stack.clear();
+				} else if let Some(app) = component.as_mut() {
+					let translated = stack.last().is_some_and(|(_, t)| *t) || attribute(&e, "xml:lang").is_some();
+					launchable = name == "launchable" && attribute(&e, "type").is_some_and(|t| t == "desktop-id");
+					// the releases are sorted (newest first)
+					if name == "release" && stack.len() == 1 && stack[0].0 == "releases" {
+						app.releases.push(Release::from_element(&e));
+					}
+					// release notes (`<releases><release><description><p>` or `<ul><li>`)
+					let note = stack.len() >= 3 && stack[0].0 == "releases" && stack[2].0 == "description"
+						&& ((name == "p" && stack.len() == 3) || (name == "li" && stack.len() == 4));
+					if note && !translated && let Some(release) = app.releases.last_mut() {
+						release.notes.push(if name == "li" { "- ".to_string() } else { String::new() });
+						note_depth = Some(stack.len() + 1);
+					}
+					stack.push((name, translated));
+				}
+			},
+			Event::Empty(e) => {
+				// releases without content (eg. `<release version="1.0" timestamp="..."/>`)
+				if let Some(app) = component.as_mut()
+					&& e.name().as_ref() == b"release" && stack.len() == 1 && stack[0].0 == "releases" {
+					app.releases.push(Release::from_element(&e));
+				}
+			},
+			Event::End(e) => {
+				if e.name().as_ref() == b"component" {
+					if let Some(app) = component.as_mut() {
+						app.version = app.releases.first().map(|r| r.version.clone()).unwrap_or_default();
+					}
+					out.extend(component.take());
+				} else {
+					stack.pop();
+					// the end of a release note (empty ones are removed)
+					if note_depth.is_some_and(|depth| stack.len() < depth) {
+						note_depth = None;
+						if let Some(release) = component.as_mut().and_then(|app| app.releases.last_mut())
+							&& release.notes.last().is_some_and(|note| note.is_empty() || note == "- ") {
+							release.notes.pop();
+						}
+					}
+				}
+			},
+			Event::Text(t) => {
+				let (Some(app), Some((element, false))) = (component.as_mut(), stack.last()) else {
+					buf.clear();
+					continue;
+				};
+				let value = t.unescape()?.to_string();
+				if note_depth.is_some() {
+					if let Some(note) = app.releases.last_mut().and_then(|release| release.notes.last_mut()) {
+						append_text(note, &value);
+					}
+					buf.clear();
+					continue;
+				}
+				match (stack.len(), element.as_str()) {
+					(1, "id") => app.id = value.trim_end_matches(".desktop").to_string(),
+					(1, "name") => app.name = value,
+					(1, "summary") => app.summary = value,
+					(1, "bundle") => app.flatpak_ref = value,
+					(1, "launchable") if launchable => app.launchables.push(value),
+					(1, "developer_name") => app.developer = value,
+					(2, "name") if stack[0].0 == "developer" => app.developer = value,
+					(2, "binary") if stack[0].0 == "provides" => app.binaries.push(value),
+					(2, "keyword") if stack[0].0 == "keywords" => app.keywords.push(value),
+					(2, "category") if stack[0].0 == "categories" => app.categories.push(value),
+					_ => {},
+				}
+			},
+			Event::Eof => break,
+			_ => {},
+		}
+		buf.clear();
+	}
+	Ok(out)
+}
+
+/// append a (trimmed) text to a release note (the parts of the text around markup are separated by a space)
+fn append_text(note: &mut String, text: &str) {
+	if !note.is_empty() && !note.ends_with(' ') && !text.starts_with([',', '.', ';', ':', '!', '?', ')']) {
+		note.push(' ');
+	}
+	note.push_str(text);
+}
+
+/// get the value of an attribute of an xml element
+fn attribute(element: &BytesStart, key: &str) -> Option<String> {
+	element.attributes()
+		.flatten()
+		.find(|a| a.key.as_ref() == key.as_bytes())
+		.map(|a| String::from_utf8_lossy(&a.value).to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const CATALOG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<components version="0.8" origin="flathub">
+  <component type="desktop">
+    <id>org.gimp.GIMP.desktop</id>
+    <name>GNU Image Manipulation Program</name>
+    <name xml:lang="de">GNU-Bildbearbeitungsprogramm</name>
+    <summary>Create images and edit photographs</summary>
+    <summary xml:lang="de">Bilder erstellen und Fotos bearbeiten</summary>
+    <developer_name>The GIMP team</developer_name>
+    <bundle type="flatpak">app/org.gimp.GIMP/x86_64/stable</bundle>
+    <launchable type="desktop-id">org.gimp.GIMP.desktop</launchable>
+    <launchable type="service">org.gimp.Service</launchable>
+    <provides>
+      <binary>gimp</binary>
+      <binary>gimp-2.10</binary>
+    </provides>
+    <categories>
+      <category>Graphics</category>
+    </categories>
+    <keywords>
+      <keyword>photo</keyword>
+      <keyword xml:lang="de">Foto</keyword>
+    </keywords>
+    <releases>
+      <release version="2.10.38" date="2024-05-02T00:00:00Z">
+        <description>
+          <p>Fixes for <em>many</em> bugs, and <code>faster</code> filters.</p>
+          <p xml:lang="de">Viele Fehler behoben.</p>
+          <ul>
+            <li>Better <em>tablet</em> support</li>
+            <li>New icons</li>
+            <li xml:lang="de">Neue Symbole</li>
+          </ul>
+          <p></p>
+        </description>
+      </release>
+      <release version="2.10.36" timestamp="1699401600"/>
+    </releases>
+  </component>
+  <component type="runtime">
+    <id>org.gnome.Platform</id>
+    <name>GNOME Platform</name>
+    <developer>
+      <name>The GNOME Project</name>
+    </developer>
+    <bundle type="flatpak">runtime/org.gnome.Platform/x86_64/46</bundle>
+  </component>
+</components>
+"#;
+
+	fn parse(input: &'static str) -> Vec<Component> {
+		parse_catalog("flathub", Box::new(input.as_bytes())).unwrap()
+	}
+
+	#[test]
+	fn parses_components() {
+		let components = parse(CATALOG);
+		assert_eq!(components.len(), 2);
+		let gimp = &components[0];
+		assert_eq!(gimp.id, "org.gimp.GIMP");
+		assert_eq!(gimp.remote, "flathub");
+		// translated elements are skipped
+		assert_eq!(gimp.name, "GNU Image Manipulation Program");
+		assert_eq!(gimp.summary, "Create images and edit photographs");
+		assert_eq!(gimp.keywords, ["photo"]);
+		assert_eq!(gimp.developer, "The GIMP team");
+		assert_eq!(gimp.flatpak_ref, "app/org.gimp.GIMP/x86_64/stable");
+		assert_eq!(gimp.launchables, ["org.gimp.GIMP.desktop"]);
+		assert_eq!(gimp.binaries, ["gimp", "gimp-2.10"]);
+		assert_eq!(gimp.categories, ["Graphics"]);
+		assert_eq!(gimp.version, "2.10.38");
+
+		let platform = &components[1];
+		assert_eq!((platform.id.as_str(), platform.name.as_str()), ("org.gnome.Platform", "GNOME Platform"));
+		assert_eq!(platform.developer, "The GNOME Project");
+		assert!(platform.releases.is_empty() && platform.version.is_empty());
+	}
+
+	#[test]
+	fn parses_releases() {
+		let components = parse(CATALOG);
+		let releases = &components[0].releases;
+		assert_eq!(releases.len(), 2);
+		assert_eq!((releases[0].version.as_str(), releases[0].date.as_str()), ("2.10.38", "2024-05-02"));
+		// markup is part of the note, translated and empty notes are skipped
+		assert_eq!(releases[0].notes, [
+			"Fixes for many bugs, and faster filters.",
+			"- Better tablet support",
+			"- New icons",
+		]);
+		assert_eq!((releases[1].version.as_str(), releases[1].date.as_str()), ("2.10.36", "2023-11-08"));
+		assert!(releases[1].notes.is_empty());
+	}
+
+	#[test]
+	fn rejects_broken_catalogs() {
+		assert!(parse("<components></components>").is_empty());
+		assert!(parse_catalog("flathub", Box::new("<components><component><id>a</name>".as_bytes())).is_err());
+	}
+}
@@ -0,0 +1,482 @@
+//! read local flatpak files (`.flatpakref` files, `.flatpak` bundles and sideload repos)
+// bundle.rs
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::error::PacpakError;
+use crate::flatpak::{installation_path, flatpak_strings};
+
+/// strings used in (or to detect) local flatpak files
+mod text {
+	/// first line of a `.flatpakref` file
+	pub const REF_HEADER: &str = "[Flatpak Ref]";
+	pub const REF_EXTENSION: &str = "flatpakref";
+	pub const BUNDLE_EXTENSION: &str = "flatpak";
+	/// (part of the) extension of pacman packages
+	pub const PACMAN_EXTENSION: &str = ".pkg.tar";
+	/// remote shown for bundles without an origin
+	pub const NO_REMOTE: &str = "(bundle)";
+	/// the repo in a sideload dir (eg. created by `flatpak create-usb`)
+	pub const SIDELOAD_REPO: &str = ".ostree/repo";
+	/// dirs of the refs in a repo (`remotes/REMOTE/REF` and `mirrors/COLLECTION/REF`)
+	pub const REFS_DIRS: [&str; 2] = ["refs/remotes", "refs/mirrors"];
+	pub const MIRRORS_DIR: &str = "refs/mirrors";
+	/// group of a remote in the config of a repo (`[remote "NAME"]`)
+	pub const REMOTE_GROUP: &str = "[remote \"";
+	pub const COLLECTION_KEY: &str = "collection-id";
+	/// group of the app in the metadata of a bundle (it has the `runtime` key)
+	pub const APPLICATION_GROUP: &str = "[Application]";
+}
+
+/// a local flatpak file (and the infos from its header)
+#[derive(Debug, Default, Clone)]
+pub struct LocalFlatpak {
+	/// the path (or url) as given on the cli
+	pub path: String,
+	/// `true` for `.flatpakref` files, `false` for bundles
+	pub is_ref_file: bool,
+	/// the ref in the file (eg. `app/org.gimp.GIMP/x86_64/stable`; `.flatpakref` files have no kind and arch, eg. `org.gimp.GIMP//stable`)
+	pub flatpak_ref: String,
+	/// name or url of the remote the app comes from
+	pub remote: String,
+	/// installed size in bytes (if known)
+	pub installed_size: Option<u64>,
+	/// the sideload repo that contains the ref (eg. for dirs created with `flatpak create-usb`)
+	pub sideload_repo: Option<String>,
+	/// the runtime a bundled app uses (eg. `org.gnome.Platform/x86_64/46`, empty if unknown)
+	pub runtime: String,
+}
+
+impl LocalFlatpak {
+	/// test if the file contains a runtime (it has to be installed before the apps that use it)
+	pub fn is_runtime(&self) -> bool {
+		self.flatpak_ref.starts_with("runtime/")
+	}
+}
+
+/// the sideload repo in a dir (`DIR/.ostree/repo`, or the dir itself if it is an OSTree repo)
+pub fn sideload_repo(dir: &str) -> Option<PathBuf> {
+	let dir = Path::new(dir);
+	[dir.join(text::SIDELOAD_REPO), dir.to_path_buf()].into_iter()
+		.find(|repo| repo.join("config").is_file() && repo.join("objects").is_dir())
+}
+
+/// read the refs of the apps in a sideload repo (or of the runtimes, if it contains no apps)
+/// returns None if the path is no sideload dir
+pub fn read_sideload_repo(path: &str) -> Result<Option<Vec<LocalFlatpak>>, PacpakError> {
+	let Some(repo) = sideload_repo(path) else {
+		return Ok(None);
+	};
+	let mut refs: Vec<LocalFlatpak> = Vec::new();
+	for refs_dir in text::REFS_DIRS.map(|dir| repo.join(dir)).iter().filter(|dir| dir.is_dir()) {
+		let mut dirs = vec![refs_dir.clone()];
+		while let Some(dir) = dirs.pop() {
+			for entry in fs::read_dir(&dir)? {
+				let entry_path = entry?.path();
+				if entry_path.is_dir() {
+					dirs.push(entry_path);
+					continue;
+				}
+				// REMOTE/KIND/ID/ARCH/BRANCH (or COLLECTION/KIND/ID/ARCH/BRANCH for mirrors)
+				let Ok(relative) = entry_path.strip_prefix(refs_dir) else { continue };
+				let parts: Vec<&str> = relative.iter().filter_map(|part| part.to_str()).collect();
+				if let [remote, kind @ ("app" | "runtime"), id, arch, branch] = parts[..] {
+					let remote = if refs_dir.ends_with(text::MIRRORS_DIR) {
+						remote_of_collection(remote).unwrap_or_else(|| format!("({})", remote))
+					} else {
+						remote.to_string()
+					};
+					refs.push(LocalFlatpak {
+						path: path.to_string(),
+						flatpak_ref: format!("{}/{}/{}/{}", kind, id, arch, branch),
+						remote,
+						sideload_repo: Some(repo.display().to_string()),
+						..Default::default()
+					});
+				}
+			}
+		}// while dir
+	}
+	if refs.iter().any(|local| local.flatpak_ref.starts_with("app/")) {
+		refs.retain(|local| local.flatpak_ref.starts_with("app/"));
+	}
+	refs.sort_by(|a, b| a.flatpak_ref.cmp(&b.flatpak_ref));
+	refs.dedup_by(|a, b| a.flatpak_ref == b.flatpak_ref);
+	Ok(Some(refs))
+}
+
+/// the name of the configured remote with a collection id (in the system or user installation)
+fn remote_of_collection(collection_id: &str) -> Option<String> {
+	["system", "user"].into_iter()
+		.filter_map(installation_path)
+		.filter_map(|installation| fs::read_to_string(installation.join(flatpak_strings::REPO_DIR).join("config")).ok())
+		.find_map(|config| {
+			let mut remote = None;
+			for line in config.lines().map(str::trim) {
+				if let Some(group) = line.strip_prefix(text::REMOTE_GROUP) {
+					remote = Some(group.trim_end_matches(['"', ']']));
+				} else if line.starts_with('[') {
+					remote = None;
+				} else if let Some((key, value)) = line.split_once('=')
+					&& key.trim() == text::COLLECTION_KEY && value.trim() == collection_id && remote.is_some()
+				{
+					return remote.map(String::from);
+				}
+			}// for line
+			None
+		})
+}
+
+/// read the bundles in a dir (eg. exported by `pacpak -Sw --cachedir`)
+/// (other files are skipped, a missing dir has no bundles)
+pub fn read_bundle_dir(dir: &str) -> Result<Vec<LocalFlatpak>, PacpakError> {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(e.into()),
+	};
+	let mut paths: Vec<PathBuf> = entries.flatten()
+		.map(|entry| entry.path())
+		.filter(|path| path.is_file() && path.extension().is_some_and(|e| e == text::BUNDLE_EXTENSION))
+		.collect();
+	paths.sort();
+	let mut out = Vec::new();
+	for path in paths {
+		out.extend(read_local(&path.to_string_lossy())?);
+	}
+	Ok(out)
+}
+
+/// read the header of a local flatpak file (detected by its extension and content)
+/// returns None for other files (eg. pacman packages)
+pub fn read_local(path: &str) -> Result<Option<LocalFlatpak>, PacpakError> {
+	let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or_default();
+	// remote .flatpakref files are handled by flatpak directly
+	if (path.starts_with("https://") || path.starts_with("http://")) && extension == text::REF_EXTENSION {
+		return Ok(Some(LocalFlatpak {
+			path: path.to_string(),
+			is_ref_file: true,
+			flatpak_ref: path.rsplit('/').next().unwrap_or(path).trim_end_matches(".flatpakref").to_string(),
+			remote: path.to_string(),
+			..Default::default()
+		}));
+	}
+	let Ok(mut file) = File::open(path) else {
+		// let pacman report missing files
+		return Ok(None);
+	};
+
+	let mut start = [0u8; 64];
+	let read = file.read(&mut start)?;
+	let is_ref_file = String::from_utf8_lossy(&start[..read]).trim_start().starts_with(text::REF_HEADER);
+	if extension == text::REF_EXTENSION || is_ref_file {
+		return read_ref_file(path).map(Some);
+	}
+	if extension == text::BUNDLE_EXTENSION {
+		return read_bundle(path, &mut file).map(Some);
+	}
+	if path.contains(text::PACMAN_EXTENSION) {
+		return Ok(None);
+	}
+	// bundles with another extension
+	Ok(read_bundle(path, &mut file).ok())
+}
+
+/// read a `.flatpakref` file (a keyfile with a `[Flatpak Ref]` group)
+fn read_ref_file(path: &str) -> Result<LocalFlatpak, PacpakError> {
+	let content = std::fs::read_to_string(path)?;
+	let mut local = LocalFlatpak {
+		path: path.to_string(),
+		is_ref_file: true,
+		..Default::default()
+	};
+	let (mut name, mut branch, mut url, mut remote_name) = (None, None, None, None);
+	for line in content.lines() {
+		if let Some((key, value)) = line.split_once('=') {
+			let value = Some(value.trim().to_string());
+			match key.trim() {
+				"Name" => name = value,
+				"Branch" => branch = value,
+				"Url" => url = value,
+				"SuggestRemoteName" => remote_name = value,
+				_ => {},
+			}
+		}
+	}// for line
+	let name = name.ok_or_else(|| PacpakError::Parse(format!("{} (no 'Name' key)", path)))?;
+	local.flatpak_ref = format!("{}//{}", name, branch.unwrap_or_default());
+	local.remote = remote_name.or(url).unwrap_or_default();
+	Ok(local)
+}
+
+/// read the metadata of a `.flatpak` bundle
+/// (the file is a serialized GVariant (an OSTree static delta superblock), that starts with an `a{sv}` of metadata)
+fn read_bundle(path: &str, file: &mut File) -> Result<LocalFlatpak, PacpakError> {
+	let parse_error = || PacpakError::Parse(format!("{} (not a flatpak bundle)", path));
+	let file_size = file.metadata()?.len();
+
+	// the end of the first member of the superblock tuple is the last framing offset of the file
+	let offset_size = offset_size(file_size);
+	if file_size < offset_size as u64 {
+		return Err(parse_error());
+	}
+	file.seek(SeekFrom::End(-(offset_size as i64)))?;
+	let mut offset = vec![0u8; offset_size];
+	file.read_exact(&mut offset)?;
+	let metadata_size = read_offset(&offset);
+	if metadata_size == 0 || metadata_size > file_size {
+		return Err(parse_error());
+	}
+
+	let mut metadata = vec![0u8; metadata_size as usize];
+	file.seek(SeekFrom::Start(0))?;
+	file.read_exact(&mut metadata)?;
+	let entries = parse_dict(&metadata).ok_or_else(parse_error)?;
+
+	let mut local = LocalFlatpak {
+		path: path.to_string(),
+		is_ref_file: false,
+		remote: text::NO_REMOTE.to_string(),
+		..Default::default()
+	};
+	for (key, value_type, value) in entries {
+		match (key.as_str(), value_type.as_str()) {
+			("ref", "s") => local.flatpak_ref = gvariant_string(value),
+			("origin", "s") => local.remote = gvariant_string(value),
+			("metadata", "s") => local.runtime = metadata_runtime(&gvariant_string(value)),
+			// flatpak stores the size in big endian
+			("installed-size", "t") if value.len() == 8 => {
+				local.installed_size = value.try_into().ok().map(u64::from_be_bytes);
+			},
+			_ => {},
+		}
+	}
+	if local.flatpak_ref.is_empty() {
+		return Err(parse_error());
+	}
+	Ok(local)
+}
+
+/// the runtime in the metadata of a bundled app (a keyfile, eg. `runtime=org.gnome.Platform/x86_64/46`)
+fn metadata_runtime(metadata: &str) -> String {
+	let mut in_application = false;
+	for line in metadata.lines().map(str::trim) {
+		if line.starts_with('[') {
+			in_application = line == text::APPLICATION_GROUP;
+		} else if in_application && let Some((key, value)) = line.split_once('=') && key.trim() == "runtime" {
+			return value.trim().to_string();
+		}
+	}
+	String::new()
+}
+
+/// size of the framing offsets of a GVariant container with the given size
+fn offset_size(container_size: u64) -> usize {
+	match container_size {
+		0..=0xff => 1,
+		0x100..=0xffff => 2,
+		0x10000..=0xffff_ffff => 4,
+		_ => 8,
+	}
+}
+
+/// read a (little endian) framing offset
+fn read_offset(bytes: &[u8]) -> u64 {
+	bytes.iter().rev().fold(0, |acc, byte| (acc << 8) | *byte as u64)
+}
+
+/// parse a serialized GVariant `a{sv}`
+/// returns tuples of (key, value type, value data)
+fn parse_dict(data: &[u8]) -> Option<Vec<(String, String, &[u8])>> {
+	let size = data.len();
+	let osize = offset_size(size as u64);
+	// the framing offsets (one per entry) start at the end of the last entry
+	let table_start = read_offset(data.get(size.checked_sub(osize)?..)?) as usize;
+	if table_start > size || !(size - table_start).is_multiple_of(osize) {
+		return None;
+	}
+	let mut out = Vec::new();
+	let mut start = 0;
+	for frame in data[table_start..].chunks(osize) {
+		let end = read_offset(frame) as usize;
+		let entry = data.get(start..end)?;
+		out.push(parse_dict_entry(entry)?);
+		// entries are aligned to 8 bytes
+		start = end.div_ceil(8) * 8;
+	}
+	Some(out)
+}
+
+/// parse a serialized GVariant `{sv}`
+fn parse_dict_entry(entry: &[u8]) -> Option<(String, String, &[u8])> {
+	let osize = offset_size(entry.len() as u64);
+	let key_end = read_offset(entry.get(entry.len().checked_sub(osize)?..)?) as usize;
+	let key = gvariant_string(entry.get(..key_end)?);
+	// the variant is aligned to 8 bytes and ends with `\0type`
+	let variant = entry.get(key_end.div_ceil(8) * 8..entry.len() - osize)?;
+	let separator = variant.iter().rposition(|byte| *byte == 0)?;
+	let value_type = String::from_utf8_lossy(&variant[separator + 1..]).to_string();
+	Some((key, value_type, &variant[..separator]))
+}
+
+/// convert a serialized GVariant string (with a trailing `\0`)
+fn gvariant_string(data: &[u8]) -> String {
+	String::from_utf8_lossy(data.strip_suffix(&[0]).unwrap_or(data)).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// serialize a `{sv}` (small enough for 1 byte framing offsets)
+	fn dict_entry(key: &str, value_type: &str, value: &[u8]) -> Vec<u8> {
+		let mut entry = key.as_bytes().to_vec();
+		entry.push(0);
+		let key_end = entry.len();
+		entry.resize(key_end.div_ceil(8) * 8, 0);
+		entry.extend_from_slice(value);
+		entry.push(0);
+		entry.extend_from_slice(value_type.as_bytes());
+		entry.push(key_end as u8);
+		entry
+	}
+
+	/// serialize an `a{sv}` (small enough for 1 byte framing offsets)
+	fn dict(entries: &[Vec<u8>]) -> Vec<u8> {
+		let mut data = Vec::new();
+		let mut offsets = Vec::new();
+		for entry in entries {
+			data.resize(data.len().div_ceil(8) * 8, 0);
+			data.extend_from_slice(entry);
+			offsets.push(data.len() as u8);
+		}
+		data.extend(offsets);
+		assert!(data.len() <= 0xff);
+		data
+	}
+
+	fn metadata() -> Vec<u8> {
+		dict(&[
+			dict_entry("ref", "s", b"app/org.test.App/x86_64/stable\0"),
+			dict_entry("origin", "s", b"flathub\0"),
+			dict_entry("installed-size", "t", &1234u64.to_be_bytes()),
+		])
+	}
+
+	#[test]
+	fn parses_dict_entries() {
+		let data = metadata();
+		let entries = parse_dict(&data).unwrap();
+		assert_eq!(entries.len(), 3);
+		assert_eq!(entries[0].0, "ref");
+		assert_eq!(entries[0].1, "s");
+		assert_eq!(gvariant_string(entries[0].2), "app/org.test.App/x86_64/stable");
+		assert_eq!((entries[1].0.as_str(), gvariant_string(entries[1].2).as_str()), ("origin", "flathub"));
+		assert_eq!(entries[2].1, "t");
+		assert_eq!(entries[2].2, 1234u64.to_be_bytes());
+	}
+
+	#[test]
+	fn parses_single_dict_entry() {
+		let entry = dict_entry("origin", "s", b"fedora\0");
+		let (key, value_type, value) = parse_dict_entry(&entry).unwrap();
+		assert_eq!((key.as_str(), value_type.as_str(), value), ("origin", "s", &b"fedora\0"[..]));
+	}
+
+	#[test]
+	fn rejects_truncated_and_misaligned_input() {
+		let data = metadata();
+		assert!(parse_dict(&[]).is_none());
+		assert!(parse_dict_entry(&[]).is_none());
+		// framing offset table beyond the data
+		let mut beyond = data.clone();
+		*beyond.last_mut().unwrap() = 0xf0;
+		assert!(parse_dict(&beyond).is_none());
+		// key end beyond the entry
+		let mut entry = dict_entry("ref", "s", b"x\0");
+		*entry.last_mut().unwrap() = 0xf0;
+		assert!(parse_dict_entry(&entry).is_none());
+		// no `\0` between the value and its type
+		assert!(parse_dict_entry(&[b'k', 0, 0, 0, 0, 0, 0, 0, b's', 2]).is_none());
+		// any truncation or corrupted byte must not panic
+		for len in 0..data.len() {
+			let _ = parse_dict(&data[..len]);
+		}
+		for index in 0..data.len() {
+			for byte in [0, 1, 7, 0x80, 0xff] {
+				let mut corrupted = data.clone();
+				corrupted[index] = byte;
+				let _ = parse_dict(&corrupted);
+			}
+		}
+	}
+
+	#[test]
+	fn reads_sideload_repos() {
+		let dir = std::env::temp_dir().join(format!("pacpak-test-sideload-{}", std::process::id()));
+		let repo = dir.join(text::SIDELOAD_REPO);
+		fs::create_dir_all(repo.join("objects")).unwrap();
+		fs::write(repo.join("config"), "[core]\nrepo_version=1\nmode=archive-z2\n").unwrap();
+		for flatpak_ref in [
+			"refs/remotes/flathub/app/org.test.App/x86_64/stable",
+			"refs/mirrors/org.test.Unknown/app/org.test.Other/x86_64/beta",
+			"refs/mirrors/org.test.Unknown/runtime/org.test.Platform/x86_64/46",
+			"refs/remotes/flathub/appstream/x86_64",
+		] {
+			let path = repo.join(flatpak_ref);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, "0123abcd\n").unwrap();
+		}
+		let dir_str = dir.to_str().unwrap();
+		let refs = read_sideload_repo(dir_str);
+		let no_repo = read_sideload_repo(repo.join("objects").to_str().unwrap());
+		fs::remove_dir_all(&dir).unwrap();
+
+		// only the apps (the runtimes are taken from the repo when they are installed)
+		let refs = refs.unwrap().unwrap();
+		let found: Vec<(&str, &str)> = refs.iter().map(|local| (local.flatpak_ref.as_str(), local.remote.as_str())).collect();
+		assert_eq!(found, [
+			("app/org.test.App/x86_64/stable", "flathub"),
+			// a collection id is no remote
+			("app/org.test.Other/x86_64/beta", "(org.test.Unknown)"),
+		]);
+		assert!(refs.iter().all(|local| local.path == dir_str && local.sideload_repo == Some(repo.display().to_string())));
+		assert!(no_repo.unwrap().is_none());
+	}
+
+	#[test]
+	fn reads_the_runtime_of_bundled_apps() {
+		let app = "[Application]\nname=org.test.App\nruntime=org.test.Platform/x86_64/46\nsdk=org.test.Sdk/x86_64/46\n\n[Context]\nshared=network;\n";
+		assert_eq!(metadata_runtime(app), "org.test.Platform/x86_64/46");
+		// runtimes (and other groups) have no runtime to install first
+		assert_eq!(metadata_runtime("[Runtime]\nname=org.test.Platform\nruntime=org.test.Platform/x86_64/46\n"), "");
+		assert_eq!(metadata_runtime("[Context]\nruntime=x\n[Application]\nname=org.test.App\n"), "");
+		assert_eq!(metadata_runtime(""), "");
+	}
+
+	#[test]
+	fn reads_bundle_metadata() {
+		// metadata, the rest of the superblock and the framing offset of the metadata
+		let mut bundle = metadata();
+		let metadata_size = bundle.len() as u8;
+		bundle.extend_from_slice(&[0xaa; 5]);
+		bundle.push(metadata_size);
+		let path = std::env::temp_dir().join(format!("pacpak-test-{}.flatpak", std::process::id()));
+		fs::write(&path, &bundle).unwrap();
+		let local = read_local(path.to_str().unwrap());
+		// truncated bundles are no bundles
+		fs::write(&path, &bundle[..bundle.len() / 2]).unwrap();
+		let truncated = read_local(path.to_str().unwrap());
+		fs::remove_file(&path).unwrap();
+
+		let local = local.unwrap().unwrap();
+		assert!(!local.is_ref_file);
+		assert_eq!(local.flatpak_ref, "app/org.test.App/x86_64/stable");
+		assert!(!local.is_runtime());
+		assert_eq!(local.remote, "flathub");
+		assert_eq!(local.installed_size, Some(1234));
+		assert!(truncated.is_err());
+	}
+}
@@ -0,0 +1,274 @@
+//! databases that pacpak builds itself (stored in the cache dir)
+// cache.rs
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::appstream::{self, Component};
+use crate::error::PacpakError;
+use crate::flatpak::{FlatpakApp, HistoryEntry};
+
+/// file names of the databases (in the cache dir)
+mod text {
+	pub const CACHE_DIR: &str = "pacpak";
+	/// the files database (for -F)
+	pub const FILES_DB: &str = "files.db";
+	/// the search index (for -Ss)
+	pub const SEARCH_DB: &str = "search.db";
+	/// separator for lists in a field (eg. the keywords)
+	pub const LIST_SEPARATOR: char = ';';
+	/// the downgrades of flatpaks (for --history, flatpak records them as updates)
+	pub const DOWNGRADES_DB: &str = "downgrades.db";
+	/// prefix of the lists of the remotes (for -Sl, eg. `remote-flathub.db`)
+	pub const REMOTE_DB_PREFIX: &str = "remote-";
+	pub const DB_EXTENSION: &str = ".db";
+}
+
+/// the cache dir of pacpak (`$XDG_CACHE_HOME/pacpak` or `~/.cache/pacpak`)
+pub fn cache_dir() -> Option<PathBuf> {
+	let base = env::var_os("XDG_CACHE_HOME")
+		.filter(|dir| !dir.is_empty())
+		.map(PathBuf::from)
+		.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+	Some(base.join(text::CACHE_DIR))
+}
+
+/// read a database (a table of tab separated values)
+/// returns None if the database does not exist (yet)
+pub fn read_table(name: &str) -> Result<Option<Vec<Vec<String>>>, PacpakError> {
+	let Some(path) = cache_dir().map(|dir| dir.join(name)) else {
+		return Ok(None);
+	};
+	let content = match fs::read_to_string(&path) {
+		Ok(content) => content,
+		Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+		Err(e) => return Err(e.into()),
+	};
+	let rows = content.lines()
+		.filter(|line| !line.is_empty())
+		.map(|line| line.split('\t').map(unescape).collect())
+		.collect();
+	Ok(Some(rows))
+}
+
+/// write a database (replaces the old one)
+pub fn write_table(name: &str, rows: &[Vec<String>]) -> Result<(), PacpakError> {
+	let dir = cache_dir()
+		.ok_or_else(|| PacpakError::Usage("no cache dir found (HOME is not set)".to_string()))?;
+	fs::create_dir_all(&dir)?;
+	let mut content = String::new();
+	for row in rows {
+		let fields: Vec<String> = row.iter().map(|field| escape(field)).collect();
+		content.push_str(&fields.join("\t"));
+		content.push('\n');
+	}
+	// write to a temporary file first (so a failed write keeps the old database)
+	let tmp = dir.join(format!("{}.part", name));
+	fs::write(&tmp, content)?;
+	fs::rename(&tmp, dir.join(name))?;
+	Ok(())
+}
+
+/// escape tabs, newlines and backslashes in a field
+fn escape(field: &str) -> String {
+	field.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+/// undo escape()
+fn unescape(field: &str) -> String {
+	let mut out = String::with_capacity(field.len());
+	let mut chars = field.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			out.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('t') => out.push('\t'),
+			Some('n') => out.push('\n'),
+			Some(other) => out.push(other),
+			None => out.push('\\'),
+		}
+	}
+	out
+}
+
+
+/// a file that a (not necessarily installed) flatpak provides
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+	/// the flatpak ref (eg. `app/org.gimp.GIMP/x86_64/stable`)
+	pub flatpak_ref: String,
+	pub remote: String,
+	pub name: String,
+	/// the path (relative to the installation, eg. `exports/bin/org.gimp.GIMP`)
+	pub path: String,
+}
+
+impl FileEntry {
+	/// the ref without its kind (eg. `org.gimp.GIMP/x86_64/stable`)
+	pub fn extid(&self) -> &str {
+		self.flatpak_ref.split_once('/').map(|(_, extid)| extid).unwrap_or(&self.flatpak_ref)
+	}
+}
+
+/// collect the files the components of AppStream catalogs provide
+/// (provided binaries, the exported binary of apps and their desktop files)
+pub fn files_from_components(components: &[Component]) -> Vec<FileEntry> {
+	let mut out = Vec::new();
+	for component in components.iter().filter(|c| !c.flatpak_ref.is_empty()) {
+		let mut paths: Vec<String> = component.binaries.iter()
+			.map(|binary| format!("bin/{}", binary))
+			.collect();
+		if component.flatpak_ref.starts_with("app/") {
+			paths.push(format!("exports/bin/{}", component.id));
+		}
+		paths.extend(component.launchables.iter()
+			.map(|desktop| format!("exports/share/applications/{}", desktop)));
+		for path in paths {
+			out.push(FileEntry {
+				flatpak_ref: component.flatpak_ref.clone(),
+				remote: component.remote.clone(),
+				name: component.name.clone(),
+				path,
+			});
+		}
+	}
+	out
+}
+
+/// read the files database
+/// returns None if it was not built yet (with -Fy)
+pub fn read_files() -> Result<Option<Vec<FileEntry>>, PacpakError> {
+	let Some(rows) = read_table(text::FILES_DB)? else {
+		return Ok(None);
+	};
+	let entries = rows.into_iter()
+		.filter_map(|row| match <[String; 4]>::try_from(row) {
+			Ok([flatpak_ref, remote, name, path]) => Some(FileEntry { flatpak_ref, remote, name, path }),
+			Err(_) => None,
+		})
+		.collect();
+	Ok(Some(entries))
+}
+
+/// write the files database
+pub fn write_files(entries: &[FileEntry]) -> Result<(), PacpakError> {
+	let rows: Vec<Vec<String>> = entries.iter()
+		.map(|e| vec![e.flatpak_ref.clone(), e.remote.clone(), e.name.clone(), e.path.clone()])
+		.collect();
+	write_table(text::FILES_DB, &rows)
+}
+
+
+/// rebuild the databases that are built from the AppStream catalogs of the remotes (files and search index)  
+/// returns the errors of catalogs that could not be read
+pub fn rebuild_appstream() -> Result<Vec<PacpakError>, PacpakError> {
+	let mut components = Vec::new();
+	let mut errors = Vec::new();
+	for (remote, path) in appstream::catalog_paths() {
+		match appstream::read_catalog(&remote, &path) {
+			Ok(list) => components.extend(list),
+			Err(e) => errors.push(e),
+		}
+	}
+	write_files(&files_from_components(&components))?;
+	write_search(&components)?;
+	Ok(errors)
+}
+
+/// read the search index
+/// returns None if it was not built yet
+pub fn read_search() -> Result<Option<Vec<Component>>, PacpakError> {
+	let Some(rows) = read_table(text::SEARCH_DB)? else {
+		return Ok(None);
+	};
+	let list = |field: String| -> Vec<String> {
+		field.split(text::LIST_SEPARATOR).filter(|v| !v.is_empty()).map(String::from).collect()
+	};
+	let components = rows.into_iter()
+		.filter_map(|row| match <[String; 11]>::try_from(row) {
+			Ok([remote, flatpak_ref, id, name, summary, version, developer, keywords, categories, binaries, launchables]) => Some(Component {
+				remote, flatpak_ref, id, name, summary, version, developer,
+				keywords: list(keywords),
+				categories: list(categories),
+				binaries: list(binaries),
+				launchables: list(launchables),
+				// not needed for searching (read from the catalogs, eg. for -Qc)
+				releases: Vec::new(),
+			}),
+			Err(_) => None,
+		})
+		.collect();
+	Ok(Some(components))
+}
+
+/// write the search index
+pub fn write_search(components: &[Component]) -> Result<(), PacpakError> {
+	let separator = text::LIST_SEPARATOR.to_string();
+	let rows: Vec<Vec<String>> = components.iter()
+		.filter(|c| !c.flatpak_ref.is_empty())
+		.map(|c| vec![
+			c.remote.clone(), c.flatpak_ref.clone(), c.id.clone(), c.name.clone(), c.summary.clone(), c.version.clone(), c.developer.clone(),
+			c.keywords.join(&separator), c.categories.join(&separator), c.binaries.join(&separator), c.launchables.join(&separator),
+		])
+		.collect();
+	write_table(text::SEARCH_DB, &rows)
+}
+
+/// read the cached list of a remote (from remote_list())
+/// returns None if the remote was never listed
+pub fn read_remote_list(remote: &str) -> Result<Option<Vec<FlatpakApp>>, PacpakError> {
+	let name = format!("{}{}{}", text::REMOTE_DB_PREFIX, remote, text::DB_EXTENSION);
+	let Some(rows) = read_table(&name)? else {
+		return Ok(None);
+	};
+	let apps = rows.into_iter()
+		.filter_map(|row| match <[String; 6]>::try_from(row) {
+			Ok([name, id, arch, branch, version, description]) => Some(FlatpakApp {
+				extid: format!("{}/{}/{}", id, arch, branch),
+				name, id, arch, branch, version, description,
+				origin: remote.to_string(),
+				..Default::default()
+			}),
+			Err(_) => None,
+		})
+		.collect();
+	Ok(Some(apps))
+}
+
+/// write the list of a remote
+pub fn write_remote_list(remote: &str, apps: &[FlatpakApp]) -> Result<(), PacpakError> {
+	let name = format!("{}{}{}", text::REMOTE_DB_PREFIX, remote, text::DB_EXTENSION);
+	let rows: Vec<Vec<String>> = apps.iter()
+		.map(|a| vec![a.name.clone(), a.id.clone(), a.arch.clone(), a.branch.clone(), a.version.clone(), a.description.clone()])
+		.collect();
+	write_table(&name, &rows)
+}
+
+/// read the downgrades (by --downgrade and --rollback)  
+/// returns an empty list if there were none yet
+pub fn read_downgrades() -> Result<Vec<HistoryEntry>, PacpakError> {
+	let rows = read_table(text::DOWNGRADES_DB)?.unwrap_or_default();
+	let entries = rows.into_iter()
+		.filter_map(|row| match <[String; 6]>::try_from(row) {
+			Ok([time, flatpak_ref, installation, remote, commit, old_commit]) => Some(HistoryEntry {
+				time, flatpak_ref, installation, remote, commit, old_commit,
+				..Default::default()
+			}),
+			Err(_) => None,
+		})
+		.collect();
+	Ok(entries)
+}
+
+/// add a downgrade to the downgrades database
+pub fn record_downgrade(entry: &HistoryEntry) -> Result<(), PacpakError> {
+	let mut rows = read_table(text::DOWNGRADES_DB)?.unwrap_or_default();
+	rows.push(vec![
+		entry.time.clone(), entry.flatpak_ref.clone(), entry.installation.clone(), entry.remote.clone(), entry.commit.clone(), entry.old_commit.clone(),
+	]);
+	write_table(text::DOWNGRADES_DB, &rows)
+}
@@ -1,73 +1,351 @@
 //! handling of cli args
 // cli.rs
 
-use clap::Parser;		// cli input parser
+use crate::error::PacpakError;
+
+/// options that only pacpak understands (not passed on to pacman)
+const PACPAK_ONLY: [&str; 17] = [
+	"remote", "exports", "related", "merged", "user", "system", "installation", "branch", "offline", "installed", "app", "runtime",
+	"history", "rollback", "downgrade", "mask", "commit",
+];
+/// options that only pacpak understands and that take a value (eg. `--remote REMOTE`)
+const PACPAK_VALUE_LONG: [&str; 4] = ["remote", "installation", "branch", "commit"];
+/// long pacman options that take a value (eg. `--ignore PKG`)
+const PACMAN_VALUE_LONG: [&str; 16] = [
+	"root", "dbpath", "cachedir", "color", "config", "gpgdir", "hookdir", "logfile",
+	"arch", "sysroot", "ignore", "ignoregroup", "overwrite", "assume-installed", "print-format", "ask",
+];
+/// short pacman options that take a value (eg. `-r ROOT`)
+const PACMAN_VALUE_SHORT: [char; 2] = ['r', 'b'];
 
 /// read pacman like command line arguments
-#[derive(Parser)]
-#[command(
-	disable_help_flag = true,
-	group(
-	clap::ArgGroup::new("operation")
-		.args(&["database", "query", "sync", "remove", "deptest", "upgrade", "files", "version", "help"])
-		.required(true)
-))]
+/// (options that pacpak does not know are passed on to pacman unchanged)
+#[derive(Default, Debug)]
 pub struct Cli {
 	/// # Operations:
 	/// Operate on the database
-	#[arg(short = 'D', long = "database", action = clap::ArgAction::SetTrue)]
 	pub database: bool,
 	/// Query the database
-	#[arg(short = 'Q', long = "query", action = clap::ArgAction::SetTrue)]
 	pub query: bool,
 	/// Remove packages
-	#[arg(short = 'R', long = "remove", action = clap::ArgAction::SetTrue)]
 	pub remove: bool,
 	/// Synchronize packages
-	#[arg(short = 'S', long = "sync", action = clap::ArgAction::SetTrue)]
 	pub sync: bool,
 	/// Check dependencies
-	#[arg(short = 'T', long = "deptest", action = clap::ArgAction::SetTrue)]
 	pub deptest: bool,
 	/// Upgrade or add packages
-	#[arg(short = 'U', long = "upgrade", action = clap::ArgAction::SetTrue)]
 	pub upgrade: bool,
 	/// Query the files database
-	#[arg(short = 'F', long = "files", action = clap::ArgAction::SetTrue)]
 	pub files: bool,
 	/// Display version and exit
-	#[arg(short = 'V', long = "version", action = clap::ArgAction::SetTrue)]
 	pub version: bool,
+	/// List the flatpak history (pacpak only)
+	pub history: bool,
+	/// Return flatpaks to their previous commit (pacpak only)
+	pub rollback: bool,
+	/// Update flatpaks to an older commit of their remote (pacpak only)
+	pub downgrade: bool,
 
-	// custom help (autogenerated help is disabled)
-	#[arg(short = 'h', long = "help", action = clap::ArgAction::SetTrue)]
+	// custom help
 	pub help: bool,
 
-	
+
 	/// # Options for operations:
-	/// Display information (for a package)
-	#[arg(short = 'i', long = "info", action = clap::ArgAction::SetTrue)]
-	pub info: bool,
+	/// Display information (for a package) (can be given twice)
+	pub info: u8,
 	/// List files (of a package)
-	#[arg(short = 'l', long = "list", action = clap::ArgAction::SetTrue)]
 	pub list: bool,
 	/// Search the package that owns the given file
-	#[arg(short = 'o', long = "owns", action = clap::ArgAction::SetTrue)]
 	pub owns: bool,
+	/// with Q: check the files of packages; with D: check the databases (can be given twice)
+	pub check: u8,
 	/// with S/Q: search online/locally; with R: recursive removal
-	#[arg(short = 's', long = "search", alias = "recursive", action = clap::ArgAction::SetTrue)]
 	pub search: bool,
-	#[arg(short = 'r', long = "root", value_name = "ROOT")]
 	pub install_root: Option<String>,
-	
+	/// the pacman database (the flatpak system installation is next to it)
+	pub dbpath: Option<String>,
+	/// Show less information (eg. only paths for -Ql)
+	pub quiet: bool,
+	/// with S: refresh the package databases (can be given twice)
+	pub refresh: u8,
+	/// with S: upgrade installed packages (can be given twice)
+	pub sysupgrade: u8,
+	/// with S: clean the package cache; with Q: show the changelog (can be given twice)
+	pub clean: u8,
+	/// do not ask for any confirmation
+	pub noconfirm: bool,
+	/// with S: do not reinstall up to date packages
+	pub needed: bool,
+	/// with S: only download packages (flatpaks are downloaded into the installation, or exported with --cachedir)
+	pub downloadonly: bool,
+	/// the dir for downloaded packages (flatpaks: a sideload repo or bundles, also passed on to pacman)
+	pub cachedir: Option<String>,
+	/// with D: mark packages as dependencies (unpin flatpak runtimes)
+	pub asdeps: bool,
+	/// with D: mark packages as explicitly installed (pin flatpak runtimes)
+	pub asexplicit: bool,
+	/// with S: packages that are not upgraded (comma separated, can be given multiple times)
+	pub ignore: Vec<String>,
+
 	//dev: more to add
-	
+
 	/// the remote for flatpak operations (eg. flathub)
-	#[arg(long = "remote", value_name = "REMOTE")]
 	pub remote: Option<String>,
+	/// with -Q/-Qs: list pacman and flatpak packages together (sorted by name)
+	pub merged: bool,
+	/// with -Ql: also list the files a flatpak exports (eg. desktop files)
+	pub exports: bool,
+	/// with -Ql: also list the files of related extensions (eg. .Locale, .Debug)
+	pub related: bool,
+	/// the flatpak installation to operate on (`system`, `user` or a custom one; None: all)
+	pub installation: Option<String>,
+	/// the architecture of flatpaks (also passed on to pacman)
+	pub arch: Option<String>,
+	/// the branch of flatpaks (eg. `stable` or `24.08`)
+	pub branch: Option<String>,
+	/// with -Ss/-Sl: only use the local databases (do not contact the remotes)
+	pub offline: bool,
+	/// with -Ss: only search installed flatpaks
+	pub installed: bool,
+	/// with -Ss/-Qs: only search flatpaks of a kind (`app` or `runtime`)
+	pub kind: Option<String>,
+	/// with --rollback/--downgrade: mask the flatpaks (so they are not upgraded again)
+	pub mask: bool,
+	/// with --downgrade: the commit (or a prefix of it) or the version to downgrade to (instead of asking)
+	pub commit: Option<String>,
 
 
 	/// Targets for operations
-	#[arg(value_name = "TARGETS")]
 	pub targets: Vec<String>,
+	/// the args for pacman (all args, except the ones only pacpak understands)
+	pub args_pacman: Vec<String>,
+}
+
+impl Cli {
+	/// parse the given args (without the program name) like pacman does
+	/// (short options can be combined, eg. `-Syyu`)
+	pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, PacpakError> {
+		let mut cli = Cli::default();
+		let mut args = args.into_iter();
+		let mut only_targets = false;
+
+		while let Some(arg) = args.next() {
+			if only_targets || arg == "-" || !arg.starts_with('-') {
+				cli.targets.push(arg.clone());
+				cli.args_pacman.push(arg);
+			} else if arg == "--" {
+				only_targets = true;
+				cli.args_pacman.push(arg);
+			} else if let Some(long) = arg.strip_prefix("--") {
+				let (name, value) = match long.split_once('=') {
+					Some((name, value)) => (name.to_string(), Some(value.to_string())),
+					None => (long.to_string(), None),
+				};
+				let takes_value = PACPAK_VALUE_LONG.contains(&name.as_str()) || PACMAN_VALUE_LONG.contains(&name.as_str());
+				let (value, value_arg) = match value {
+					Some(value) => (Some(value), None),
+					None if takes_value => {
+						let value = args.next()
+							.ok_or_else(|| PacpakError::Usage(format!("option '--{}' requires an argument", name)))?;
+						(Some(value.clone()), Some(value))
+					},
+					None => (None, None),
+				};
+				cli.set_long(&name, value);
+				if !PACPAK_ONLY.contains(&name.as_str()) {
+					cli.args_pacman.push(arg);
+					cli.args_pacman.extend(value_arg);
+				}
+			} else {
+				// combined short options
+				let flags: Vec<char> = arg.chars().skip(1).collect();
+				let mut value_arg = None;
+				for (i, flag) in flags.iter().enumerate() {
+					if PACMAN_VALUE_SHORT.contains(flag) {
+						// the rest of the arg (or the next arg) is the value
+						let rest: String = flags[i+1..].iter().collect();
+						let value = if rest.is_empty() {
+							let value = args.next()
+								.ok_or_else(|| PacpakError::Usage(format!("option requires an argument -- '{}'", flag)))?;
+							value_arg = Some(value.clone());
+							value
+						} else {
+							rest
+						};
+						cli.set_short(*flag, Some(value));
+						break;
+					}
+					cli.set_short(*flag, None);
+				}//for flag
+				cli.args_pacman.push(arg);
+				cli.args_pacman.extend(value_arg);
+			}
+		}// while arg
+
+		let operations = [cli.database, cli.query, cli.remove, cli.sync, cli.deptest, cli.upgrade, cli.files, cli.version, cli.help, cli.history, cli.rollback, cli.downgrade]
+			.iter()
+			.filter(|op| **op)
+			.count();
+		match operations {
+			0 => Err(PacpakError::Usage("no operation specified (use -h for help)".to_string())),
+			1 => Ok(cli),
+			_ if cli.help || cli.version => Ok(cli),
+			_ => Err(PacpakError::Usage("only one operation may be used at a time".to_string())),
+		}
+	}
+
+	/// set the field for a short option (unknown options are ignored)
+	fn set_short(&mut self, flag: char, value: Option<String>) {
+		match flag {
+			'D' => self.database = true,
+			'Q' => self.query = true,
+			'R' => self.remove = true,
+			'S' => self.sync = true,
+			'T' => self.deptest = true,
+			'U' => self.upgrade = true,
+			'F' => self.files = true,
+			'V' => self.version = true,
+			'h' => self.help = true,
+			'i' => self.info += 1,
+			'l' => self.list = true,
+			'o' => self.owns = true,
+			'k' => self.check += 1,
+			's' => self.search = true,
+			'q' => self.quiet = true,
+			'y' => self.refresh += 1,
+			'u' => self.sysupgrade += 1,
+			'c' => self.clean += 1,
+			'w' => self.downloadonly = true,
+			'r' => self.install_root = value,
+			'b' => self.dbpath = value,
+			_ => {},
+		}
+	}
+
+	/// set the field for a long option (unknown options are ignored)
+	fn set_long(&mut self, name: &str, value: Option<String>) {
+		match name {
+			"database" => self.set_short('D', value),
+			"query" => self.set_short('Q', value),
+			"remove" => self.set_short('R', value),
+			"sync" => self.set_short('S', value),
+			"deptest" => self.set_short('T', value),
+			"upgrade" => self.set_short('U', value),
+			"files" => self.set_short('F', value),
+			"version" => self.set_short('V', value),
+			"help" => self.set_short('h', value),
+			"info" => self.set_short('i', value),
+			"list" => self.set_short('l', value),
+			"owns" => self.set_short('o', value),
+			"search" | "recursive" => self.set_short('s', value),
+			"quiet" => self.set_short('q', value),
+			"refresh" => self.set_short('y', value),
+			"sysupgrade" => self.set_short('u', value),
+			"clean" => self.set_short('c', value),
+			"root" => self.set_short('r', value),
+			"dbpath" => self.set_short('b', value),
+			"noconfirm" => self.noconfirm = true,
+			"confirm" => self.noconfirm = false,
+			"needed" => self.needed = true,
+			"downloadonly" => self.set_short('w', value),
+			"cachedir" => self.cachedir = value,
+			"asdeps" => self.asdeps = true,
+			"asexplicit" => self.asexplicit = true,
+			"ignore" => self.ignore.extend(value.iter().flat_map(|v| v.split(',')).map(String::from)),
+			"remote" => self.remote = value,
+			"merged" => self.merged = true,
+			"exports" => self.exports = true,
+			"related" => self.related = true,
+			"user" => self.installation = Some("user".to_string()),
+			"system" => self.installation = Some("system".to_string()),
+			"installation" => self.installation = value,
+			"arch" => self.arch = value,
+			"branch" => self.branch = value,
+			"offline" => self.offline = true,
+			"installed" => self.installed = true,
+			"app" | "runtime" => self.kind = Some(name.to_string()),
+			"history" => self.history = true,
+			"rollback" => self.rollback = true,
+			"downgrade" => self.downgrade = true,
+			"mask" => self.mask = true,
+			"commit" => self.commit = value,
+			_ => {},
+		}
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// parse the args of a command line (without the program name)
+	fn parse(line: &str) -> Result<Cli, PacpakError> {
+		Cli::parse(line.split_whitespace().map(String::from))
+	}
+
+	#[test]
+	fn counts_repeated_short_flags() {
+		let cli = parse("-Syyu").unwrap();
+		assert!(cli.sync);
+		assert_eq!(cli.refresh, 2);
+		assert_eq!(cli.sysupgrade, 1);
+		assert_eq!(parse("-Qii").unwrap().info, 2);
+		assert_eq!(parse("-Scc").unwrap().clean, 2);
+		assert_eq!(parse("-Qkk").unwrap().check, 2);
+		assert_eq!(parse("--sync --refresh --refresh").unwrap().refresh, 2);
+	}
+
+	#[test]
+	fn reads_short_option_values() {
+		let cli = parse("-Q -r /mnt gimp").unwrap();
+		assert_eq!(cli.install_root.as_deref(), Some("/mnt"));
+		assert_eq!(cli.targets, ["gimp"]);
+		assert_eq!(cli.args_pacman, ["-Q", "-r", "/mnt", "gimp"]);
+		// the rest of a combined arg is the value
+		let cli = parse("-Qr/mnt").unwrap();
+		assert!(cli.query);
+		assert_eq!(cli.install_root.as_deref(), Some("/mnt"));
+		assert!(cli.targets.is_empty());
+		assert!(parse("-Q -r").is_err());
+		let cli = parse("-Qb /mnt/var/lib/pacman").unwrap();
+		assert_eq!(cli.dbpath.as_deref(), Some("/mnt/var/lib/pacman"));
+		assert_eq!(parse("-Q --dbpath=/db").unwrap().dbpath.as_deref(), Some("/db"));
+	}
+
+	#[test]
+	fn reads_long_option_values() {
+		let cli = parse("-S --remote=flathub --branch stable --ignore a,b --ignore=c gimp").unwrap();
+		assert_eq!(cli.remote.as_deref(), Some("flathub"));
+		assert_eq!(cli.branch.as_deref(), Some("stable"));
+		assert_eq!(cli.ignore, ["a", "b", "c"]);
+		assert_eq!(cli.targets, ["gimp"]);
+		assert!(parse("-S --remote").is_err());
+	}
+
+	#[test]
+	fn passes_unknown_options_to_pacman() {
+		let cli = parse("-S --needed --overwrite /usr/* --remote flathub --noconfirm --user vlc").unwrap();
+		assert!(cli.needed);
+		assert!(cli.noconfirm);
+		assert_eq!(cli.installation.as_deref(), Some("user"));
+		// pacpak only options (and their values) are not passed on
+		assert_eq!(cli.args_pacman, ["-S", "--needed", "--overwrite", "/usr/*", "--noconfirm", "vlc"]);
+	}
+
+	#[test]
+	fn treats_args_after_double_dash_as_targets() {
+		let cli = parse("-R -- -weird --remote").unwrap();
+		assert_eq!(cli.targets, ["-weird", "--remote"]);
+		assert!(cli.remote.is_none());
+		assert_eq!(cli.args_pacman, ["-R", "--", "-weird", "--remote"]);
+	}
+
+	#[test]
+	fn needs_exactly_one_operation() {
+		assert!(parse("gimp").is_err());
+		assert!(parse("-S -R gimp").is_err());
+		assert!(parse("-S -h").is_ok());
+		assert!(parse("--history").unwrap().history);
+	}
 }
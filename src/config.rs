@@ -0,0 +1,167 @@
+//! settings of pacpak (and reading them from the config file)
+// config.rs
+
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::error::PacpakError;
+use crate::flatpak::ref_matches;
+
+/// paths of the config files
+mod text {
+	pub const SYSTEM_CONFIG: &str = "/etc/pacpak.conf";
+	/// (in $XDG_CONFIG_HOME or ~/.config)
+	pub const USER_CONFIG: &str = "pacpak/pacpak.conf";
+}
+
+/// store (user) settings
+pub struct Config {
+	pub wrap_pacman: bool,
+	pub color:		bool,
+	/// flatpaks (ids or ref globs) that are not upgraded
+	pub ignore_pkg: Vec<String>,
+	/// like ignore_pkg, but also matches the related refs (eg. `org.gimp.GIMP` matches `org.gimp.GIMP.Locale`)
+	/// (flatpak has no groups, so pacman's `IgnoreGroup` is not used)
+	pub ignore_related: Vec<String>,
+	/// flatpaks (ids or ref globs) that are only removed after a confirmation
+	pub hold_pkg: Vec<String>,
+}
+/// standart values for the settings
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			wrap_pacman: true,
+			color:		true,
+			ignore_pkg: Vec::new(),
+			ignore_related: Vec::new(),
+			hold_pkg: Vec::new(),
+		}
+	}
+}
+
+impl Config {
+	/// read the config files (`/etc/pacpak.conf`, then `~/.config/pacpak/pacpak.conf`)
+	/// (the syntax is the one of pacman.conf: `Key = value value`, lists can be given multiple times)
+	pub fn load(&mut self) -> Result<(), PacpakError> {
+		let user_config = env::var_os("XDG_CONFIG_HOME")
+			.filter(|dir| !dir.is_empty())
+			.map(PathBuf::from)
+			.or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+			.map(|dir| dir.join(text::USER_CONFIG));
+		for path in [Some(PathBuf::from(text::SYSTEM_CONFIG)), user_config].into_iter().flatten() {
+			let content = match fs::read_to_string(&path) {
+				Ok(content) => content,
+				Err(e) if e.kind() == ErrorKind::NotFound => continue,
+				Err(e) => return Err(e.into()),
+			};
+			for line in content.lines() {
+				let line = line.split('#').next().unwrap_or_default().trim();
+				// sections (eg. `[options]`) and empty lines
+				if line.is_empty() || line.starts_with('[') {
+					continue;
+				}
+				// options without a value (eg. `Color`) are not used by pacpak
+				let Some((key, value)) = line.split_once('=') else {
+					continue;
+				};
+				let values = value.split_whitespace().map(String::from);
+				match key.trim() {
+					"IgnorePkg" => self.ignore_pkg.extend(values),
+					"IgnoreRelated" => self.ignore_related.extend(values),
+					"HoldPkg" => self.hold_pkg.extend(values),
+					_ => {},
+				}
+			}// for line
+		}
+		Ok(())
+	}
+
+	/// test if upgrades of a flatpak (given as full ref) should be ignored
+	pub fn is_ignored(&self, flatpak_ref: &str) -> bool {
+		self.ignore_pkg.iter().any(|pattern| ref_matches(pattern, flatpak_ref))
+			|| self.ignore_related.iter().any(|pattern| ref_matches(pattern, flatpak_ref) || ref_matches(&group_pattern(pattern), flatpak_ref))
+	}
+
+	/// test if a flatpak (given as full ref) should only be removed after a confirmation
+	pub fn is_held(&self, flatpak_ref: &str) -> bool {
+		self.hold_pkg.iter().any(|pattern| ref_matches(pattern, flatpak_ref))
+	}
+}
+
+/// extend the id of a pattern to also match the related refs (eg. `org.gimp.GIMP//stable` -> `org.gimp.GIMP.*//stable`)
+fn group_pattern(pattern: &str) -> String {
+	let (kind, rest) = match pattern.split_once('/') {
+		Some((kind, rest)) if kind == "app" || kind == "runtime" => (format!("{}/", kind), rest),
+		_ => (String::new(), pattern),
+	};
+	match rest.split_once('/') {
+		Some((id, rest)) => format!("{}{}.*/{}", kind, id, rest),
+		None => format!("{}{}.*", kind, rest),
+	}
+}
+
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(ignore_pkg: &[&str], ignore_related: &[&str], hold_pkg: &[&str]) -> Config {
+		let list = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect();
+		Config {
+			ignore_pkg: list(ignore_pkg),
+			ignore_related: list(ignore_related),
+			hold_pkg: list(hold_pkg),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn extends_patterns_to_related_refs() {
+		let table = [
+			("org.gimp.GIMP", "org.gimp.GIMP.*"),
+			("org.gimp.GIMP//stable", "org.gimp.GIMP.*//stable"),
+			("org.gimp.GIMP/x86_64", "org.gimp.GIMP.*/x86_64"),
+			("app/org.gimp.GIMP", "app/org.gimp.GIMP.*"),
+			("runtime/org.gnome.Platform//46", "runtime/org.gnome.Platform.*//46"),
+		];
+		for (pattern, expected) in table {
+			assert_eq!(group_pattern(pattern), expected);
+		}
+	}
+
+	#[test]
+	fn ignores_refs() {
+		let config = config(&["org.gimp.GIMP//beta", "runtime/org.kde.*"], &["org.gnome.Platform"], &[]);
+		let table = [
+			("app/org.gimp.GIMP/x86_64/beta", true),
+			("app/org.gimp.GIMP/x86_64/stable", false),
+			// IgnorePkg does not match the related refs
+			("runtime/org.gimp.GIMP.Locale/x86_64/beta", false),
+			("runtime/org.kde.Platform/x86_64/6.7", true),
+			("app/org.kde.kate/x86_64/stable", false),
+			// IgnoreRelated also matches the related refs (eg. translations and extensions)
+			("runtime/org.gnome.Platform/x86_64/46", true),
+			("runtime/org.gnome.Platform.Locale/x86_64/46", true),
+			("runtime/org.gnome.Platform.GL.default/x86_64/46", true),
+			("runtime/org.gnome.PlatformX/x86_64/46", false),
+			("runtime/org.gnome.Sdk/x86_64/46", false),
+		];
+		for (flatpak_ref, expected) in table {
+			assert_eq!(config.is_ignored(flatpak_ref), expected, "{}", flatpak_ref);
+		}
+		assert!(!Config::default().is_ignored("app/org.gimp.GIMP/x86_64/stable"));
+	}
+
+	#[test]
+	fn holds_refs() {
+		let config = config(&["org.gimp.GIMP"], &[], &["org.freedesktop.Platform*", "app/*//beta"]);
+		assert!(config.is_held("runtime/org.freedesktop.Platform/x86_64/24.08"));
+		assert!(config.is_held("runtime/org.freedesktop.Platform.GL.default/x86_64/24.08"));
+		assert!(config.is_held("app/org.gimp.GIMP/x86_64/beta"));
+		assert!(!config.is_held("runtime/org.gnome.Platform/x86_64/beta"));
+		// IgnorePkg does not hold refs
+		assert!(!config.is_held("app/org.gimp.GIMP/x86_64/stable"));
+	}
+}
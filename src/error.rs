@@ -0,0 +1,95 @@
+//! error type of pacpak
+// error.rs
+
+use std::fmt;
+use std::io;
+use colored::Colorize;	// format output strings (for the terminal)
+
+use crate::exit_status;
+use crate::text;
+
+/// everything that can go wrong in pacpak
+/// (printed in the style of pacman: `error: message`)
+#[derive(Debug)]
+pub enum PacpakError {
+	/// the flatpak executable could not be run
+	FlatpakMissing,
+	/// the pacman executable could not be run
+	PacmanMissing,
+	/// a called command returned a non-zero exit status
+	CommandFailed {
+		/// the command (with its most important args, eg. `flatpak list`)
+		command: String,
+		stderr: String,
+		code: Option<i32>,
+	},
+	/// no package for the given target
+	TargetNotFound(String),
+	/// a plain name matches more than one package (the candidates are listed)
+	AmbiguousTarget(String, Vec<String>),
+	/// the flatpak history has no commit to roll a flatpak back to
+	NoPreviousCommit(String),
+	/// an operation needs targets, but none were given
+	NoTargets,
+	/// the cli args are invalid (eg. no operation)
+	Usage(String),
+	/// the output of a command (or a file) could not be understood
+	Parse(String),
+	/// missing permissions (eg. for a file or a system installation)
+	PermissionDenied(String),
+	/// other io failures
+	Io(io::Error),
+}
+
+impl PacpakError {
+	/// the exit status pacpak should exit with for this error
+	pub fn exit_status(&self) -> i32 {
+		match self {
+			PacpakError::FlatpakMissing | PacpakError::PacmanMissing => exit_status::MISSING,
+			PacpakError::CommandFailed { code, .. } => code.unwrap_or(exit_status::ERROR),
+			PacpakError::TargetNotFound(_) | PacpakError::NoTargets | PacpakError::NoPreviousCommit(_) => exit_status::NOT_FOUND,
+			PacpakError::PermissionDenied(_) | PacpakError::Usage(_) | PacpakError::AmbiguousTarget(..) => exit_status::FAILURE,
+			PacpakError::Parse(_) | PacpakError::Io(_) => exit_status::ERROR,
+		}
+	}
+}
+
+impl fmt::Display for PacpakError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} ", text::ERROR_PREFIX.red().bold())?;
+		match self {
+			PacpakError::FlatpakMissing => write!(f, "failed to execute flatpak (is it installed?)"),
+			PacpakError::PacmanMissing => write!(f, "failed to execute pacman"),
+			PacpakError::CommandFailed { command, stderr, .. } => {
+				let stderr = stderr.trim();
+				if stderr.is_empty() {
+					write!(f, "command '{}' failed", command)
+				} else {
+					write!(f, "command '{}' failed: {}", command, stderr)
+				}
+			},
+			PacpakError::TargetNotFound(target) => write!(f, "target not found: {}", target),
+			PacpakError::AmbiguousTarget(target, candidates) => {
+				write!(f, "target is ambiguous: {} (candidates: {})", target, candidates.join(", "))
+			},
+			PacpakError::NoPreviousCommit(extid) => write!(f, "{} {}", text::NO_PREVIOUS_COMMIT, extid),
+			PacpakError::NoTargets => write!(f, "{}", text::NO_TARGETS),
+			PacpakError::Usage(message) => write!(f, "{}", message),
+			PacpakError::Parse(what) => write!(f, "failed to parse {}", what),
+			PacpakError::PermissionDenied(what) => write!(f, "permission denied: {}", what),
+			PacpakError::Io(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for PacpakError {}
+
+impl From<io::Error> for PacpakError {
+	fn from(e: io::Error) -> Self {
+		if e.kind() == io::ErrorKind::PermissionDenied {
+			PacpakError::PermissionDenied(e.to_string())
+		} else {
+			PacpakError::Io(e)
+		}
+	}
+}
@@ -1,23 +1,52 @@
 //! fetch data from flatpak (over its cli)
 // flatpak.rs
 
-use std::process::Command;
-use std::io;
+use std::process::{Command, Stdio};
+use std::io::{self, Read};
 use std::fs;
+use std::env;
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 use chrono::{DateTime, Local};
 
+use crate::error::PacpakError;
+use crate::search::{Field, Matcher};
+
 /// string constants (eg. for errors or meta field values)
 mod text {
 	pub const VERSION_UNKOWN: &str = "?";
 	//pub const NONE: &str = "None";
 	pub const NOT_IMPLEMENTED: &str = "[not implemented]";
 	pub const SKIPPED: &str = "[skipped]";
+	pub const NO_SUCH_FILE: &str = "No such file or directory";
+	pub const SIZE_MISMATCH: &str = "Size mismatch";
+	pub const TYPE_MISMATCH: &str = "File type mismatch";
 }
 
 /// strings from flatpak commands (or their output)
 pub mod flatpak_strings {
 	// return string when no results where found for `flatpak search TEXT`
 	pub const SEARCH_NO_RESULTS: &str = "No matches found";
+	/// option to run flatpak without asking questions (like pacman --noconfirm)
+	pub const NONINTERACTIVE: &str = "--noninteractive";
+	/// option to answer all questions with yes (the user was already asked by pacpak)
+	pub const ASSUME_YES: &str = "--assumeyes";
+	/// the OSTree repo of an installation
+	pub const REPO_DIR: &str = "repo";
+	/// dir for temporary files of the repo of an installation (eg. partial downloads)
+	pub const REPO_TMP_DIR: &str = "repo/tmp";
+	/// the types of OSTree objects (`flatpak repair` reports problems as `...: CHECKSUM.TYPE`)
+	pub const OBJECT_TYPES: [&str; 9] = [
+		"file", "dirtree", "dirmeta", "commit", "tombstone-commit", "commitmeta", "payload-link", "file-xattrs", "file-xattrs-link",
+	];
+	/// the start of the commits in `flatpak remote-info --log`
+	pub const LOG_HEADER: &str = "History:";
+	/// path of the system installation (relative to the root)
+	pub const SYSTEM_DIR: &str = "var/lib/flatpak";
+	/// id suffixes of the extensions that flatpak installs along with an app
+	pub const RELATED_SUFFIXES: [&str; 3] = ["Locale", "Debug", "Sources"];
 }
 
 /// flatpak app metadata
@@ -39,6 +68,8 @@ pub struct FlatpakApp {
 	/// can be `system` or `user`
 	pub installation: String,
 	pub install_size: String,
+	/// only for remote apps (eg. with -Si)
+	pub download_size: String,
 	pub runtime: String,
 	pub sdk: String,
 	pub commit: String,
@@ -53,9 +84,34 @@ pub struct FlatpakApp {
 	pub provides: String,
 	pub packager: String,
 	pub depends: String,
+	/// the command that is run by `flatpak run` (empty for runtimes)
+	pub command: String,
+	/// `app` or `runtime`
+	pub kind: String,
+	/// runtimes only: pinned (explicitly installed, kept by `flatpak uninstall --unused`)
+	pub pinned: bool,
 	//pub v: String,
 }
 
+impl FlatpakApp {
+	/// the flatpak option that selects the installation of this app (eg. `--user`)
+	pub fn installation_arg(&self) -> String {
+		installation_arg(&self.installation)
+	}
+
+	/// the full ref of this app (eg. `app/org.gimp.GIMP/x86_64/stable`)  
+	/// (needs the kind, see FlatpakMeta::get_kind())
+	pub fn full_ref(&self) -> String {
+		format!("{}/{}", self.kind, self.extid)
+	}
+
+	/// the dir into which the installation of this app links the exported files (eg. `/var/lib/flatpak/exports`)  
+	/// returns None if the path of the installation is unknown
+	pub fn exports_dir(&self) -> Option<PathBuf> {
+		installation_path(&self.installation).map(|installation| installation.join("exports"))
+	}
+}
+
 /// flatpak meta object (houses all (app) metadata)
 #[derive(Default, Clone)]
 pub struct FlatpakMeta {
@@ -65,32 +121,513 @@ pub struct FlatpakMeta {
 	pub list_small: String,
 	/// output of `flatpak list` with many columns
 	pub list_full: String,
+	/// index of the app locations (built on first use)
+	owners: Option<OwnerIndex>,
+	/// pinned patterns of each installation (fetched on first use)
+	pins: HashMap<String, Vec<String>>,
+	/// the installation to operate on (`system`, `user` or a custom one; None: all)
+	pub installation: Option<String>,
+}
+
+/// a change of a ref in the flatpak history
+#[derive(Debug, Default, Clone)]
+pub struct HistoryEntry {
+	pub time: String,
+	/// eg. `deploy install`, `deploy update` or `uninstall`
+	pub change: String,
+	/// the full ref (eg. `app/org.gimp.GIMP/x86_64/stable`)
+	pub flatpak_ref: String,
+	pub installation: String,
+	pub remote: String,
+	pub commit: String,
+	/// the commit before the change (empty for installs)
+	pub old_commit: String,
+	pub version: String,
+}
+
+/// the result of checking the files of a deployment (for -Qk)
+#[derive(Debug, Default)]
+pub struct FileCheck {
+	/// number of files (and dirs) in the deployment
+	pub total: usize,
+	/// missing files with the reason (eg. `No such file or directory`)
+	pub missing: Vec<(PathBuf, String)>,
+	/// files that differ from the commit with the reason (eg. `Size mismatch`)
+	pub altered: Vec<(PathBuf, String)>,
+}
+
+/// prefix index of the (canonical) deploy locations of all (installed flatpak) apps  
+/// (used to quickly find the app that owns a path)
+#[derive(Default, Clone)]
+pub struct OwnerIndex {
+	/// deploy location -> index (for FlatpakMeta::apps)
+	locations: HashMap<PathBuf, usize>,
+}
+
+impl OwnerIndex {
+	/// find the app whose deploy location contains `path` (which should be canonical)  
+	/// returns an index (for FlatpakMeta::apps)
+	pub fn lookup(&self, path: &Path) -> Option<usize> {
+		path.ancestors().find_map(|dir| self.locations.get(dir).copied())
+	}
+}
+
+/// get the (flatpak) name of the arch of this system (eg. `x86_64`)
+pub fn default_arch() -> String {
+	match env::consts::ARCH {
+		"x86" => "i386".to_string(),
+		arch => arch.to_string(),
+	}
+}
+
+/// the path of the system installation, if it is not the default one (set with -r/--root)
+static SYSTEM_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// use another path for the system installation (eg. `/mnt/var/lib/flatpak`)  
+/// (for pacpak and every flatpak command it runs)
+pub fn set_system_dir(path: PathBuf) {
+	let _ = SYSTEM_DIR.set(path);
+}
+
+/// the system installation for -r/--root and -b/--dbpath (None for the default one)  
+/// (like pacman: the dbpath overrides the root, and the installation is next to it, eg. `/mnt/var/lib/pacman` -> `/mnt/var/lib/flatpak`)
+pub fn alternate_system_dir(root: Option<&str>, dbpath: Option<&str>) -> Option<PathBuf> {
+	match (root, dbpath) {
+		(_, Some(dbpath)) => Some(Path::new(dbpath).parent().unwrap_or(Path::new("/")).join("flatpak")),
+		(Some(root), None) => Some(Path::new(root).join(flatpak_strings::SYSTEM_DIR)),
+		(None, None) => None,
+	}
+}
+
+/// create a command that runs flatpak (with the system installation set by set_system_dir())
+pub fn flatpak_command() -> Command {
+	flatpak_command_in(SYSTEM_DIR.get().map(PathBuf::as_path))
+}
+
+/// create a command that runs flatpak with another system installation (None: the default one)
+fn flatpak_command_in(system_dir: Option<&Path>) -> Command {
+	let mut command = Command::new("flatpak");
+	if let Some(dir) = system_dir {
+		command.env("FLATPAK_SYSTEM_DIR", dir);
+	}
+	command
+}
+
+/// the path of the system installation (the given one, $FLATPAK_SYSTEM_DIR or `/var/lib/flatpak`)
+fn system_installation_path(system_dir: Option<&Path>) -> PathBuf {
+	system_dir.map(Path::to_path_buf)
+		.or_else(|| env::var_os("FLATPAK_SYSTEM_DIR").map(PathBuf::from))
+		.unwrap_or_else(|| Path::new("/").join(flatpak_strings::SYSTEM_DIR))
+}
+
+/// the flatpak option that selects an installation (`system`, `user` or the id/name of a custom installation)
+pub fn installation_arg(name: &str) -> String {
+	match name {
+		"system" | "default" | "" => "--system".to_string(),
+		"user" => "--user".to_string(),
+		name => format!("--installation={}", name),
+	}
+}
+
+/// get the path of a flatpak installation (`system`, `user` or the id/name of a custom installation)
+pub fn installation_path(name: &str) -> Option<PathBuf> {
+	match name {
+		"system" | "default" => Some(system_installation_path(SYSTEM_DIR.get().map(PathBuf::as_path))),
+		"user" => env::var_os("FLATPAK_USER_DIR")
+			.map(PathBuf::from)
+			.or_else(|| env::var_os("XDG_DATA_HOME").map(|dir| PathBuf::from(dir).join("flatpak")))
+			.or_else(|| env::var_os("HOME").map(|dir| PathBuf::from(dir).join(".local/share/flatpak"))),
+		_ => {
+			// custom installations are configured in `[Installation "id"]` groups
+			let conf_dir = env::var_os("FLATPAK_CONFIG_DIR")
+				.map(PathBuf::from)
+				.unwrap_or_else(|| PathBuf::from("/etc/flatpak"))
+				.join("installations.d");
+			for entry in fs::read_dir(conf_dir).ok()?.flatten() {
+				let Ok(conf) = fs::read_to_string(entry.path()) else { continue };
+				let mut matching = false;
+				let mut path = None;
+				for line in conf.lines() {
+					let line = line.trim();
+					if let Some(group) = line.strip_prefix("[Installation \"") {
+						if matching && path.is_some() { break; }
+						matching = group.trim_end_matches(['"', ']']) == name;
+						path = None;
+					} else if let Some((key, value)) = line.split_once('=') {
+						match key.trim() {
+							"Path" => path = Some(PathBuf::from(value.trim())),
+							"DisplayName" if value.trim() == name => matching = true,
+							_ => {},
+						}
+					}
+				}// for line
+				if matching && path.is_some() {
+					return path;
+				}
+			}// for entry
+			None
+		},
+	}
+}
+
+/// the disk usage of a directory in bytes  
+/// (like `du`: hard linked files are counted once, symlinks are not followed)
+pub fn disk_usage(path: &Path) -> u64 {
+	let mut seen: HashSet<(u64, u64)> = HashSet::new();
+	let mut total = 0;
+	let mut dirs = vec![path.to_path_buf()];
+	while let Some(dir) = dirs.pop() {
+		let Ok(entries) = fs::read_dir(&dir) else { continue };
+		for entry in entries.flatten() {
+			let Ok(meta) = entry.metadata() else { continue };
+			if meta.is_dir() {
+				dirs.push(entry.path());
+			}
+			if seen.insert((meta.dev(), meta.ino())) {
+				total += meta.blocks() * 512;
+			}
+		}
+	}// while dir
+	total
+}
+
+/// remove the temporary files in the repo of an installation (eg. stale downloads)
+pub fn remove_repo_tmp(installation: &Path) -> Result<(), PacpakError> {
+	let tmp = installation.join(flatpak_strings::REPO_TMP_DIR);
+	let entries = match fs::read_dir(&tmp) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(e) => return Err(e.into()),
+	};
+	for entry in entries {
+		let path = entry?.path();
+		if path.is_dir() && !path.is_symlink() {
+			fs::remove_dir_all(&path)?;
+		} else {
+			fs::remove_file(&path)?;
+		}
+	}
+	Ok(())
+}
+
+/// check the objects of an installation with `flatpak repair --dry-run` (for -Qkk and -Dk)  
+/// returns the problems of each ref (eg. `app/org.gimp.GIMP/x86_64/stable`, problems of no ref have an empty key)
+pub fn repair_dry_run(installation: &str) -> Result<HashMap<String, Vec<String>>, PacpakError> {
+	let args = ["repair", "--dry-run", &installation_arg(installation)];
+	// the refs are printed on stdout and the problems on stderr (read from one pipe, to keep their order)
+	let (mut reader, writer) = io::pipe()?;
+	let mut command = flatpak_command();
+	command.args(args)
+		.stdin(Stdio::null())
+		.stdout(writer.try_clone()?)
+		.stderr(writer);
+	let mut child = command.spawn()
+		.map_err(|e| match e.kind() {
+			io::ErrorKind::NotFound => PacpakError::FlatpakMissing,
+			_ => e.into(),
+		})?;
+	// close the write ends of the command, so the pipe ends with the child
+	drop(command);
+	let mut output = String::new();
+	reader.read_to_string(&mut output)?;
+	let status = child.wait()?;
+	let problems = parse_repair(&output);
+	if !status.success() && problems.is_empty() {
+		return Err(PacpakError::CommandFailed { command: "flatpak repair".to_string(), stderr: output, code: status.code() });
+	}
+	Ok(problems)
+}
+
+/// parse the output of `flatpak repair` (stdout and stderr)  
+/// (the messages are translated, so only their fixed parts are used: the refs (`[1/76] Verifying flathub:app/org.gimp.GIMP/x86_64/stable…`)
+/// and the objects of the problems (`Object missing: CHECKSUM.file`))
+fn parse_repair(output: &str) -> HashMap<String, Vec<String>> {
+	let mut problems: HashMap<String, Vec<String>> = HashMap::new();
+	let mut current = String::new();
+	for line in output.lines().map(str::trim) {
+		let is_object = |word: &str| word.trim_end_matches([',', ':']).split_once('.')
+			.is_some_and(|(checksum, kind)| {
+				checksum.len() == 64 && checksum.chars().all(|c| c.is_ascii_hexdigit())
+					&& flatpak_strings::OBJECT_TYPES.contains(&kind)
+			});
+		if line.split_whitespace().any(is_object) {
+			problems.entry(current.clone()).or_default().push(line.to_string());
+			continue;
+		}
+		// `REMOTE:KIND/ID/ARCH/BRANCH` (the end of the line can be `…` or `...`)
+		let refspec = line.split_whitespace()
+			.map(|word| word.trim_end_matches(['…', '.']))
+			.map(|word| word.split_once(':').map_or(word, |(_, flatpak_ref)| flatpak_ref))
+			.find(|word| (word.starts_with("app/") || word.starts_with("runtime/")) && word.split('/').count() == 4);
+		if let Some(refspec) = refspec {
+			current = refspec.to_string();
+		}
+	}// for line
+	problems
+}
+
+/// list the files in a dir of a commit with `ostree ls` (for check_files())  
+/// returns tuples of (type (`d`, `-` or `l`), size, path)
+fn commit_files(repo: &Path, commit: &str, dir: &str) -> Result<Vec<(char, u64, String)>, PacpakError> {
+	let repo_arg = format!("--repo={}", repo.display());
+	let output = Command::new("ostree")
+		.args(["ls", "-R", &repo_arg, commit, dir])
+		.output()?;
+	if !output.status.success() {
+		let stderr = String::from_utf8_lossy(&output.stderr).into();
+		return Err(PacpakError::CommandFailed { command: "ostree ls".to_string(), stderr, code: output.status.code() });
+	}
+	Ok(parse_ostree_ls(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// parse the output of `ostree ls` (`MODE UID GID SIZE PATH`, symlinks end with ` -> TARGET`)
+fn parse_ostree_ls(output: &str) -> Vec<(char, u64, String)> {
+	output.lines()
+		.filter_map(|line| {
+			let mut rest = line.trim_start();
+			let mut fields = Vec::new();
+			for _ in 0..4 {
+				let (field, tail) = rest.split_once(char::is_whitespace)?;
+				fields.push(field);
+				rest = tail.trim_start();
+			}
+			let kind = fields[0].chars().next()?;
+			let size = fields[3].parse().ok()?;
+			let path = if kind == 'l' { rest.split_once(" -> ").map_or(rest, |(path, _)| path) } else { rest };
+			Some((kind, size, path.to_string()))
+		})
+		.collect()
+}
+
+/// test if a ref (eg. `runtime/org.gnome.Platform/x86_64/46`) matches a pattern  
+/// (patterns are (partial) refs with globs, like `org.gnome.*`, `runtime/org.gnome.Platform//46` or `*/x86_64/*`;
+/// missing or empty parts match every value)
+pub fn ref_matches(pattern: &str, flatpak_ref: &str) -> bool {
+	let mut ref_parts: Vec<&str> = flatpak_ref.splitn(4, '/').collect();
+	let pattern = match pattern.split_once('/') {
+		Some((kind, rest)) if kind == "app" || kind == "runtime" => {
+			if ref_parts.first() != Some(&kind) {
+				return false;
+			}
+			rest
+		},
+		_ => pattern,
+	};
+	if matches!(ref_parts.first(), Some(&"app") | Some(&"runtime")) {
+		ref_parts.remove(0);
+	}
+	pattern.split('/')
+		.enumerate()
+		.all(|(i, part)| part.is_empty() || ref_parts.get(i).is_some_and(|value| glob_match(part, value)))
+}
+
+/// match a text against a glob pattern (with `*` and `?`)
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+	let (pattern, text): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+	let (mut p, mut t) = (0, 0);
+	// position after the last `*` (and the text position it was tried at)
+	let mut backtrack: Option<(usize, usize)> = None;
+	while t < text.len() {
+		if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+			p += 1;
+			t += 1;
+		} else if p < pattern.len() && pattern[p] == '*' {
+			p += 1;
+			backtrack = Some((p, t));
+		} else if let Some((after_star, tried)) = backtrack {
+			// let the `*` match one more character
+			p = after_star;
+			t = tried + 1;
+			backtrack = Some((after_star, t));
+		} else {
+			return false;
+		}
+	}
+	pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// parse the output of `flatpak info` (or `flatpak remote-info`) into an app
+fn parse_info(app: &mut FlatpakApp, info_str: &str) {
+	// before stuff
+	app.depends = "flatpak ".to_string();
+
+	let mut desc_read = false;	// detect multiline descriptions
+	for line in info_str.lines() {
+
+		if desc_read {
+			// detect multiline descriptions
+			if line.is_empty() {
+				desc_read = false;
+			} else {
+				let pre_space = "		  ";	// space before the next line of description
+				app.description += &format!("\n{}{}",pre_space,line.trim());
+			}
+		} else if let Some((key, value)) = line.split_once(':') {
+			let key = key.trim();
+			let value = value.trim();
+
+			match key {
+				"ID"     => app.id     = value.to_string(),
+				"Ref"    => app.kind   = value.split('/').next().unwrap_or_default().to_string(),
+				//"Name"   => app.name   = value.to_string(),
+				"Arch"   => app.arch   = value.to_string(),
+				"Branch" => app.branch = value.to_string(),
+				"Version" => app.version = value.to_string(),
+				"License" => app.license = value.to_string(),
+				"Origin" => app.origin = value.to_string(),
+				"Collection" => app.collection = value.to_string(),
+				"Installation" => app.installation = value.to_string(),
+				"Installed" => app.install_size = value.to_string(),
+				"Download" => app.download_size = value.to_string(),
+				"Runtime" => {
+					app.runtime = value.to_string();
+					if !value.is_empty() {
+						app.depends += value;
+					}
+				},
+				"Sdk" => app.sdk = value.to_string(),
+				
+				"Commit" => app.commit = value.to_string(),
+				"Parent" => app.parent = value.to_string(),
+				"Subject" => app.subject = value.to_string(),
+				"Date" => app.build_date = format_date(value),
+				_ => {}, // ignore unknown keys
+			}//match
+		} else if let Some((name, value)) = line.split_once('-') {
+			app.name = name.trim().into();
+			app.description = value.trim().into();
+			desc_read = true;
+		}
+	}// for line
+	
+	// after stuff (or unimplemented fields)
+	if app.version.is_empty() { app.version = text::VERSION_UNKOWN.to_string(); }
+	if app.license.is_empty() { app.license = text::VERSION_UNKOWN.to_string(); }
+	app.packager = text::NOT_IMPLEMENTED.to_string();
+	app.url = text::NOT_IMPLEMENTED.to_string();
+	app.provides = text::NOT_IMPLEMENTED.to_string();
+}
+
+/// convert a date of flatpak (eg. `2024-06-10 12:00:00 +0000`) into the pacman format  
+/// (other dates are kept)
+fn format_date(date: &str) -> String {
+	match DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S %z") {
+		Ok(time) => time.format("%a %d %b %Y %I:%M:%S %p %Z").to_string(),
+		Err(_) => date.to_string(),
+	}
+}
+
+/// parse the output of `flatpak history` (with the columns of FlatpakMeta::history())  
+/// (changes without a ref, eg. `pull` of the appstream data, are skipped)
+fn parse_history(history_str: &str) -> Vec<HistoryEntry> {
+	history_str.lines()
+		.filter_map(|line| {
+			let columns: Vec<&str> = line.split('\t').map(str::trim).collect();
+			match columns[..] {
+				[time, change, flatpak_ref, installation, remote, commit, old_commit, version] if !flatpak_ref.is_empty() => Some(HistoryEntry {
+					time: time.into(),
+					change: change.into(),
+					flatpak_ref: flatpak_ref.into(),
+					installation: installation.into(),
+					remote: remote.into(),
+					commit: commit.into(),
+					old_commit: old_commit.into(),
+					version: version.into(),
+				}),
+				_ => None,
+			}
+		})
+		.collect()
+}
+
+/// parse the output of `flatpak remote-info --log` into the commits of an app (the newest first)  
+/// (each commit is a copy of the app with its commit, subject and date)
+fn parse_log(info_str: &str) -> Vec<FlatpakApp> {
+	// the info of the app, then `History:` and the commits
+	let (info, log) = match info_str.split_once(flatpak_strings::LOG_HEADER) {
+		Some((info, log)) => (info, log),
+		None => (info_str, ""),
+	};
+	let mut app = FlatpakApp::default();
+	parse_info(&mut app, info);
+	app.extid = format!("{}/{}/{}", app.id, app.arch, app.branch);
+
+	let mut out: Vec<FlatpakApp> = Vec::new();
+	for line in log.lines() {
+		let Some((key, value)) = line.split_once(':') else {
+			continue;
+		};
+		let value = value.trim();
+		match key.trim() {
+			// every commit starts with its hash
+			"Commit" => out.push(FlatpakApp {
+				commit: value.to_string(),
+				subject: String::new(),
+				build_date: String::new(),
+				// only the latest commit has a known version
+				version: if out.is_empty() { app.version.clone() } else { text::VERSION_UNKOWN.to_string() },
+				..app.clone()
+			}),
+			"Subject" => if let Some(commit) = out.last_mut() { commit.subject = value.to_string() },
+			"Date" => if let Some(commit) = out.last_mut() { commit.build_date = format_date(value) },
+			_ => {},
+		}
+	}// for line
+	// without a log: only the latest commit
+	if out.is_empty() && !app.commit.is_empty() {
+		out.push(app);
+	}
+	out
+}
+
+/// run flatpak with the given args (pipe buffers)  
+/// returns its stdout (or an error with its stderr)
+pub fn flatpak_output(args: &[&str]) -> Result<String, PacpakError> {
+	let output = flatpak_command()
+		.args(args)
+		.output()
+		.map_err(|e| match e.kind() {
+			io::ErrorKind::NotFound => PacpakError::FlatpakMissing,
+			_ => e.into(),
+		})?;
+	if !output.status.success() {
+		let stderr: String = String::from_utf8_lossy(&output.stderr).into();
+		let command = format!("flatpak {}", args.first().unwrap_or(&""));
+		if stderr.to_lowercase().contains("permission denied") || stderr.contains("Not allowed") {
+			return Err(PacpakError::PermissionDenied(command));
+		}
+		return Err(PacpakError::CommandFailed { command, stderr, code: output.status.code() });
+	}
+	Ok(String::from_utf8_lossy(&output.stdout).into())
 }
 
 impl FlatpakMeta {
 	// can be instantiated with Default::default()
+
+	/// the flatpak options for the selected installation (empty if all installations are used)
+	pub fn scope_args(&self) -> Vec<String> {
+		self.installation.iter().map(|name| installation_arg(name)).collect()
+	}
 	
 	/// fetch a basic list off all (installed flatpak) apps from the flatpak cli
 	/// (overwrites the current self.apps vector)
-	pub fn get_apps(&mut self) -> io::Result<&Vec<FlatpakApp>> {
-		let flatpak_list_raw = Command::new("flatpak")
-			.args(["list", "--columns=application,arch,branch,origin"])
-			.output()?;
-		if !flatpak_list_raw.status.success() {
-			return Err(io::Error::new(io::ErrorKind::Other, "command: 'flatpak list' failed")); //dev
-		}
-		self.list_small = String::from_utf8_lossy(&flatpak_list_raw.stdout).into();
+	pub fn get_apps(&mut self) -> Result<&Vec<FlatpakApp>, PacpakError> {
+		let scope = self.scope_args();
+		let mut args = vec!["list", "--columns=application,arch,branch,origin,installation"];
+		args.extend(scope.iter().map(String::as_str));
+		self.list_small = flatpak_output(&args)?;
 		let flatpak_list_str = &self.list_small;
 		let mut apps: Vec<FlatpakApp> = flatpak_list_str
 			.lines()
 			.filter_map(|line| {
 				let columns: Vec<&str> = line.split('\t').collect();
-				if columns.len() == 4 {
+				if columns.len() == 5 {
 					Some(FlatpakApp {
 						id: columns[0].into(),
 						arch: columns[1].into(),
 						branch: columns[2].into(),
 						origin: columns[3].into(),
+						installation: columns[4].into(),
 						..Default::default()
 					})
 				} else {
@@ -103,35 +640,18 @@ impl FlatpakMeta {
 			app.extid = format!("{}/{}/{}", app.id, app.arch, app.branch);
 		}
 		self.apps = apps;
+		self.owners = None;
 		Ok(&self.apps)
 	}
 	
-	/// searches for programs in self.apps, with a name/id similar to `input`
-	/// returns a vector of indexes (for self.apps)
-	pub fn search_apps(&mut self, input: &Vec<&str>) -> Vec<usize> {
-		//println!("{:?}",input);
-		let mut out : Vec<usize> = Vec::new();
-		if input.len() > 0 && !input[0].is_empty() {
-			for text in input { 
-				let text = text.to_lowercase();
-				
-				for (i, app) in self.apps.iter().enumerate() {
-					if app.extid.to_lowercase().contains(&text) || app.name.to_lowercase().contains(&text) {
-						if !out.contains(&i) { out.push(i); }
-						//println!("YES: {}",extid)
-					}
-				}// for (i, app)
-			}// for text
-			out.sort();
-		} else {
-			// output all indexes if no string was given
-			out = (0..self.apps.len()).collect();
-		}
-		return out;
-    }
-	/// same as FlatpakMeta::search_apps(), but also searches in the description/origin
+	/// searches for programs in self.apps that match the search terms
+	/// returns a vector of indexes (for self.apps), the most relevant first
+	pub fn search_apps(&self, matcher: &Matcher) -> Vec<usize> {
+		matcher.rank(&self.apps)
+	}
+	/// same as FlatpakMeta::search_apps(), but fetches the description/origin first (so they can be searched)
 	/// returns a vector of indexes (for self.apps)
-	pub fn search_apps_desc(&mut self, input: &Vec<&str>) -> Vec<usize> {
+	pub fn search_apps_desc(&mut self, matcher: &Matcher) -> Vec<usize> {
 		// fetch some unfilled fields (for self)
 		for i in 0..self.apps.len() {
 			let app = &self.apps[i];
@@ -139,154 +659,115 @@ impl FlatpakMeta {
 				let _ = self.get_app_info_full(i);
 			}
 		}
+		self.search_apps(matcher)
+	}
+	/// searches for the installed app that matches a ref (`id`, `id/arch` or `id/arch/branch`)  
+	/// (empty parts match every value, a missing arch matches the arch of this system)  
+	/// returns an index (for self.apps)
+	pub fn search_ref(&self, target: &str) -> Option<usize> {
+		self.search_refs(target).first().copied()
+	}
+	/// like search_ref(), but returns all matching apps (eg. every branch of `id//`)
+	pub fn search_refs(&self, target: &str) -> Vec<usize> {
+		let mut parts = target.splitn(3, '/');
+		let id = parts.next().unwrap_or_default();
+		let arch = parts.next().map(String::from).unwrap_or_else(default_arch);
+		let branch = parts.next().unwrap_or_default();
+		(0..self.apps.len())
+			.filter(|i| {
+				let app = &self.apps[*i];
+				app.id == id
+					&& (arch.is_empty() || app.arch == arch)
+					&& (branch.is_empty() || app.branch == branch)
+			})
+			.collect()
+	}
 
-		let mut out : Vec<usize> = Vec::new();
-		if input.len() > 0 && !input[0].is_empty() {
-			for text in input { 
-				let text = text.to_lowercase();
-				
-				for (i, app) in self.apps.iter().enumerate() {
-					if app.extid.to_lowercase().contains(&text) 
-						|| app.name.to_lowercase().contains(&text) 
-						|| app.description.to_lowercase().contains(&text)
-						|| app.origin.to_lowercase().contains(&text) {
-						if !out.contains(&i) { out.push(i); }
-						//println!("YES: {}",extid)
-					}
-				}// for (i, app)
-			}// for text
-			out.sort();
-		} else {
-			// output all indexes if no string was given
-			out = (0..self.apps.len()).collect();
+	/// select the installed apps for the targets of eg. -Q, -Qi or -R  
+	/// targets are refs (`id`, `id/arch`, `id//branch` or `id/arch/branch`), missing parts are taken from `arch`/`branch`
+	/// (eg. --arch/--branch) or match every value; targets without a `/` that are no id are searched by their id and name  
+	/// returns a vector of indexes (for self.apps), in the order of the targets (the best match first)
+	pub fn select_apps(&mut self, targets: &[&str], arch: Option<&str>, branch: Option<&str>) -> Vec<usize> {
+		let selected = |app: &FlatpakApp, arch: Option<&str>, branch: Option<&str>| {
+			arch.is_none_or(|arch| app.arch == arch) && branch.is_none_or(|branch| app.branch == branch)
+		};
+		if targets.is_empty() {
+			return (0..self.apps.len()).filter(|i| selected(&self.apps[*i], arch, branch)).collect();
 		}
-		return out;
-    }
+		let mut out: Vec<usize> = Vec::new();
+		for target in targets {
+			let mut parts = target.splitn(3, '/');
+			let id = parts.next().unwrap_or_default();
+			let target_arch = parts.next().filter(|part| !part.is_empty()).or(arch);
+			let target_branch = parts.next().filter(|part| !part.is_empty()).or(branch);
+			let mut matches: Vec<usize> = (0..self.apps.len())
+				.filter(|i| self.apps[*i].id == id && selected(&self.apps[*i], target_arch, target_branch))
+				.collect();
+			if matches.is_empty() && !target.contains('/') {
+				matches = self.search_apps(&Matcher::literal(&[target], &[Field::Id, Field::Name])).into_iter()
+					.filter(|i| selected(&self.apps[*i], arch, branch))
+					.collect();
+			}
+			for i in matches {
+				if !out.contains(&i) {
+					out.push(i);
+				}
+			}
+		}// for target
+		out
+	}
+
 	/// get some basic infos about a (flatpak) app
-	pub fn get_app_info(&mut self, idx: usize) -> io::Result<&FlatpakApp> {
+	pub fn get_app_info(&mut self, idx: usize) -> Result<&FlatpakApp, PacpakError> {
 		if self.list_full.is_empty() {
-			let flatpak_list_raw = Command::new("flatpak")
-				.args(["list", "--columns=name,application,arch,branch,version,application"])
-				.output()?;
-			if !flatpak_list_raw.status.success() {
-				return Err(io::Error::new(io::ErrorKind::Other, "command: 'flatpak list' failed")); //dev
-			}
-			self.list_full = String::from_utf8_lossy(&flatpak_list_raw.stdout).into();
+			let scope = self.scope_args();
+			let mut args = vec!["list", "--columns=name,application,arch,branch,version,application,installation"];
+			args.extend(scope.iter().map(String::as_str));
+			self.list_full = flatpak_output(&args)?;
 		}
 		
-		let searchterms = [&self.apps[idx].id, &self.apps[idx].arch, &self.apps[idx].branch];
+		let app = &self.apps[idx];
+		// columns: name, id, arch, branch, version, id, installation
+		let searchterms = [&app.id, &app.arch, &app.branch];
 		if let Some(matching) = self.list_full
 			.lines()
-			.find(|line| searchterms.iter().all(|k| line.contains(k.as_str())))
+			.find(|line| {
+				let columns: Vec<&str> = line.split('\t').collect();
+				// lines with too few columns are skipped
+				columns.len() >= 5 && searchterms.iter().enumerate().all(|(i, k)| columns[i + 1] == k.as_str())
+					&& columns.get(6).is_none_or(|installation| *installation == app.installation)
+			})
 		{
 			let columns: Vec<&str> = matching.split('\t').collect();
-			if columns.len() >= 4 {
-				self.apps[idx].name = columns[0].into();
-				self.apps[idx].version = columns[4].into();
-                if self.apps[idx].version.is_empty() {
-                    self.apps[idx].version = "?".to_string();
-                }
-			} else {
-				//dev failed
-                eprintln!("error stuff: app has to few columns");
-			}
+			self.apps[idx].name = columns[0].into();
+			self.apps[idx].version = columns[4].into();
+            if self.apps[idx].version.is_empty() {
+                self.apps[idx].version = text::VERSION_UNKOWN.to_string();
+            }
 		} else {
-			//dev failed
-			eprintln!("error stuff: app not found in str");
+			return Err(PacpakError::Parse(format!("'flatpak list' ({} is missing)", self.apps[idx].extid)));
 		}
-		//println!("{:?}", matching);
 		
 		Ok(&self.apps[idx])
 	}
 
 	/// get detailed infos about a (flatpak) app
-	pub fn get_app_info_full(&mut self, idx: usize) -> io::Result<&FlatpakApp> {
-		let flatpak_info_raw = Command::new("flatpak")
-			.args(["info", &self.apps[idx].extid])
-			.output()?;
-		if !flatpak_info_raw.status.success() {
-			return Err(io::Error::new(io::ErrorKind::Other, "command: 'flatpak info' failed")); //dev
-		}
-		let info_str: String = String::from_utf8_lossy(&flatpak_info_raw.stdout).into();
+	pub fn get_app_info_full(&mut self, idx: usize) -> Result<&FlatpakApp, PacpakError> {
+		let info_str: String = flatpak_output(&["info", &self.apps[idx].installation_arg(), &self.apps[idx].extid])?;
 
-		// before stuff
-		let app = &mut self.apps[idx];
-		app.depends = "flatpak ".to_string();
-
-		let mut desc_read = false;	// detect multiline descriptions
-		for line in info_str.lines() {
-
-			if desc_read {
-				// detect multiline descriptions
-				if line.is_empty() {
-					desc_read = false;
-				} else {
-					let pre_space = "		  ";	// space before the next line of description
-					app.description += &format!("\n{}{}",pre_space,line.trim());
-				}
-			} else if let Some((key, value)) = line.split_once(':') {
-				let key = key.trim();
-				let value = value.trim();
-
-				match key {
-					"ID"     => app.id     = value.to_string(),
-					//"Name"   => app.name   = value.to_string(),
-					"Arch"   => app.arch   = value.to_string(),
-					"Branch" => app.branch = value.to_string(),
-					"Version" => app.version = value.to_string(),
-					"License" => app.license = value.to_string(),
-					"Origin" => app.origin = value.to_string(),
-					"Collection" => app.collection = value.to_string(),
-					"Installation" => app.installation = value.to_string(),
-					"Installed" => app.install_size = value.to_string(),
-					"Runtime" => {
-						app.runtime = value.to_string();
-						if !value.is_empty() {
-							app.depends += value;
-						}
-					},
-					"Sdk" => app.sdk = value.to_string(),
-					
-					"Commit" => app.commit = value.to_string(),
-					"Parent" => app.parent = value.to_string(),
-					"Subject" => app.subject = value.to_string(),
-					"Date" => {
-						app.build_date = value.to_string();
-						// try to parse the date into the pacman format
-						if let Ok(time) = DateTime::parse_from_str(&app.build_date, "%Y-%m-%d %H:%M:%S %z") {
-							app.build_date = time.format("%a %d %b %Y %I:%M:%S %p %Z").to_string();
-						}
-					},
-					_ => {}, // ignore unknown keys
-				}//match
-			} else if let Some((name, value)) = line.split_once('-') {
-				app.name = name.trim().into();
-				app.description = value.trim().into();
-				desc_read = true;
-			}
-		}// for line
-		
-		// after stuff (or unimplemented fields)
-		if app.version.is_empty() { app.version = text::VERSION_UNKOWN.to_string(); }
-		if app.license.is_empty() { app.license = text::VERSION_UNKOWN.to_string(); }
-		app.packager = text::NOT_IMPLEMENTED.to_string();
-		app.url = text::NOT_IMPLEMENTED.to_string();
-		app.provides = text::NOT_IMPLEMENTED.to_string();
+		parse_info(&mut self.apps[idx], &info_str);
 		
 		// calc / fetch other fields
 		if true {
-			let _ = self.get_location(idx);
+			self.get_location(idx)?;
 			let app = &mut self.apps[idx];
 			
 			// get install date
-			let meta = fs::metadata(&app.location);
-			if let Err(e) = meta {
-				return Err(io::Error::new(io::ErrorKind::Other, format!("command: 'flatpak info --show-location' failed: {}", e))); //dev
-			}
-			let modified_time = &meta?.modified()?;
+			let modified_time = &fs::metadata(&app.location)?.modified()?;
 			let datetime: DateTime<Local> = (*modified_time).into();
 			app.install_date = datetime.format("%a %d %b %Y %I:%M:%S %p %Z").to_string();
 		} else {
+			let app = &mut self.apps[idx];
 			app.location = text::SKIPPED.to_string();
 			app.install_date = text::SKIPPED.to_string();
 		}
@@ -295,81 +776,385 @@ impl FlatpakMeta {
 	}
 	
 	/// get the location of a (flatpak) app
-	pub fn get_location(&mut self, idx: usize) -> io::Result<&FlatpakApp> {
-		let location = Command::new("flatpak")
-			.args(["info", "--show-location", &self.apps[idx].extid])
-			.output()?;
-		self.apps[idx].location = String::from_utf8_lossy(&location.stdout)
+	pub fn get_location(&mut self, idx: usize) -> Result<&FlatpakApp, PacpakError> {
+		// the location can mostly be derived from the installation path (without calling flatpak)
+		if let Some(location) = self.deploy_dir(idx) {
+			self.apps[idx].location = location.display().to_string();
+			return Ok(&self.apps[idx]);
+		}
+		self.apps[idx].location = flatpak_output(&["info", "--show-location", &self.apps[idx].installation_arg(), &self.apps[idx].extid])?
 			.trim_end()
 			.into();
 		Ok(&self.apps[idx])
 	}
 
-	/// get a list of dependencies
-	/// return a vector of self.apps indexes
-	#[deprecated]
-	pub fn get_dependencies(&mut self, idx: usize) -> io::Result<&FlatpakApp> {
-		let depends_raw = Command::new("flatpak")
-			.args(["info", "--show-runtime", "--show-extensions", &self.apps[idx].extid])
-			.output()?;
-		let depends = String::from_utf8_lossy(&depends_raw.stdout)
-			.trim_end()
-			.into();
-		if depends == "-" {
-			println!("test");
+	/// get the kind of a (flatpak) app (`app` or `runtime`)
+	pub fn get_kind(&mut self, idx: usize) -> Result<&FlatpakApp, PacpakError> {
+		if !self.apps[idx].kind.is_empty() {
+			return Ok(&self.apps[idx]);
 		}
-		self.apps[idx].depends = depends;
+		let app = &self.apps[idx];
+		let kind = match installation_path(&app.installation) {
+			Some(installation) if installation.join("app").join(&app.id).join(&app.arch).join(&app.branch).exists() => "app".to_string(),
+			Some(installation) if installation.join("runtime").join(&app.id).join(&app.arch).join(&app.branch).exists() => "runtime".to_string(),
+			_ => {
+				let flatpak_ref = flatpak_output(&["info", "--show-ref", &app.installation_arg(), &app.extid])?;
+				flatpak_ref.split('/').next().unwrap_or_default().trim().to_string()
+			},
+		};
+		self.apps[idx].kind = kind;
 		Ok(&self.apps[idx])
 	}
-	
-	/// get a list of all files that belong to a (flatpak) app
+
+	/// get if a (flatpak) runtime is pinned (apps are never pinned)
+	pub fn get_pinned(&mut self, idx: usize) -> Result<&FlatpakApp, PacpakError> {
+		self.get_kind(idx)?;
+		let arg = self.apps[idx].installation_arg();
+		if !self.pins.contains_key(&arg) {
+			// output: a header line, then one pattern per line
+			let output = flatpak_output(&["pin", &arg])?;
+			let patterns = output.lines()
+				.map(str::trim)
+				.filter(|line| line.contains('/') && !line.ends_with(':'))
+				.map(String::from)
+				.collect();
+			self.pins.insert(arg.clone(), patterns);
+		}
+		let app = &self.apps[idx];
+		let pinned = app.kind == "runtime"
+			&& self.pins[&arg].iter().any(|pattern| ref_matches(pattern, &app.full_ref()));
+		self.apps[idx].pinned = pinned;
+		Ok(&self.apps[idx])
+	}
+
+	/// get the (canonical) deploy directory of an app in its installation
+	fn deploy_dir(&self, idx: usize) -> Option<PathBuf> {
+		let app = &self.apps[idx];
+		let installation = installation_path(&app.installation)?;
+		["app", "runtime"].iter()
+			.map(|kind| installation.join(kind).join(&app.id).join(&app.arch).join(&app.branch).join("active"))
+			.find_map(|path| fs::canonicalize(path).ok())
+	}
+
+	/// get the index of all app locations (built once, fetches missing locations)
+	pub fn get_owner_index(&mut self) -> &OwnerIndex {
+		if self.owners.is_none() {
+			let mut index = OwnerIndex::default();
+			for i in 0..self.apps.len() {
+				if self.apps[i].location.is_empty() {
+					let _ = self.get_location(i);
+				}
+				if let Ok(location) = fs::canonicalize(&self.apps[i].location) {
+					index.locations.insert(location, i);
+				}
+			}
+			self.owners = Some(index);
+		}
+		self.owners.get_or_insert_default()
+	}
+
+	/// get the command of a (flatpak) app (from the metadata file in its location)
+	pub fn get_command(&mut self, idx: usize) -> Result<&FlatpakApp, PacpakError> {
+		if self.apps[idx].location.is_empty() {
+			self.get_location(idx)?;
+		}
+		let metadata = fs::read_to_string(format!("{}/metadata", self.apps[idx].location))?;
+		let mut in_application = false;
+		for line in metadata.lines() {
+			let line = line.trim();
+			if line.starts_with('[') {
+				in_application = line == "[Application]";
+			} else if in_application && let Some((key, value)) = line.split_once('=') && key.trim() == "command" {
+				self.apps[idx].command = value.trim().to_string();
+			}
+		}// for line
+		Ok(&self.apps[idx])
+	}
+
+	/// searches for the app that provides the command `name`  
+	/// (either its command or the last part of its id, eg. `gimp` for `org.gimp.GIMP`)  
+	/// returns an index (for self.apps)
+	pub fn search_command(&mut self, name: &str) -> Option<usize> {
+		for i in 0..self.apps.len() {
+			if self.apps[i].command.is_empty() {
+				let _ = self.get_command(i);
+			}
+			let app = &self.apps[i];
+			if app.command == name {
+				return Some(i);
+			}
+		}
+		self.apps.iter().position(|app| {
+			!app.command.is_empty()
+				&& app.id.rsplit('.').next().is_some_and(|last| last.eq_ignore_ascii_case(name))
+		})
+	}
+
+	/// searches for the app that exported a file (eg. `org.gimp.GIMP.desktop`)  
+	/// returns an index (for self.apps), the app with the longest matching id wins
+	pub fn search_export(&self, file_name: &str) -> Option<usize> {
+		self.apps.iter()
+			.enumerate()
+			.filter(|(_, app)| {
+				file_name.strip_prefix(app.id.as_str())
+					.is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '-']))
+			})
+			.max_by_key(|(_, app)| app.id.len())
+			.map(|(i, _)| i)
+	}
+
+	/// get a list of all files that belong to a (flatpak) app  
+	/// (relative to its `files/` tree, directories end with a `/`)  
+	/// with `exports`: also list the exported files (as absolute paths in the installation)  
 	/// returns a vector of paths (as string)
-	pub fn get_app_files(&mut self, idx: usize) -> io::Result<Vec<String>> {
-		let mut out:Vec<String> = Vec::new();
+	pub fn get_app_files(&mut self, idx: usize, exports: bool) -> Result<Vec<String>, PacpakError> {
 		// fetch app location, if needed
 		if self.apps[idx].location.is_empty() {
-			let _ = self.get_location(idx);
+			self.get_location(idx)?;
 		}
-		out.push(self.apps[idx].location.clone());
+		let location = PathBuf::from(&self.apps[idx].location);
 		
 		// list files/folders recursively
-		let mut rec_files = Self::rec_file_explorer(&self.apps[idx].location)?;
-		out.append(&mut rec_files);
+		let mut out: Vec<String> = Self::rec_file_explorer(&location.join("files"))?
+			.iter()
+			.map(|(path, is_dir)| if *is_dir { format!("{}/", path.display()) } else { path.display().to_string() })
+			.collect();
 		
+		// `deploy/export/...` is linked into `installation/exports/...`
+		if exports && let Some(exports_dir) = self.apps[idx].exports_dir()
+			&& let Ok(exported) = Self::rec_file_explorer(&location.join("export")) {
+			out.extend(exported.iter()
+				.filter(|(path, is_dir)| !is_dir && exports_dir.join(path).exists())
+				.map(|(path, _)| exports_dir.join(path).display().to_string()));
+		}
 		Ok(out)
 	}
-	/// list files/folders recursively
-	fn rec_file_explorer(path: &str) -> io::Result<Vec<String>> {
+	/// list files/folders recursively (relative to `root`)  
+	/// symlinks are not followed (so cycles are not possible) and `.ref` files are skipped  
+	/// returns tuples of (path, is_dir)
+	fn rec_file_explorer(root: &Path) -> io::Result<Vec<(PathBuf, bool)>> {
 		let mut out = Vec::new();
-		let directory = fs::read_dir(path)?;
-		for entry in directory {
-			let entry = entry?;
-			let path = entry.path();
-			let path_string = path.display().to_string();
-			out.push(path_string.clone());
-			
-			if path.is_dir() {
-				let mut rec_out = Self::rec_file_explorer(&path_string)?;
-				out.append(&mut rec_out);
+		let mut dirs = vec![PathBuf::new()];
+		while let Some(dir) = dirs.pop() {
+			for entry in fs::read_dir(root.join(&dir))? {
+				let entry = entry?;
+				if entry.file_name() == ".ref" {
+					continue;
+				}
+				let path = dir.join(entry.file_name());
+				// file_type() does not follow symlinks
+				let is_dir = entry.file_type()?.is_dir();
+				if is_dir {
+					dirs.push(path.clone());
+				}
+				out.push((path, is_dir));
+			}//for entry
+		}
+		// sorted by components, so directories come before their content
+		out.sort();
+		Ok(out)
+	}
+
+	/// check the files of a deployment against its commit (for -Qk)  
+	/// (needs the kind, see get_kind(); the files of the commit are listed with `ostree ls`, missing files and files
+	/// with another type or size are reported; the exported files are checked too)
+	pub fn check_files(&self, idx: usize) -> Result<FileCheck, PacpakError> {
+		let mut check = FileCheck::default();
+		let app = &self.apps[idx];
+		let installation = installation_path(&app.installation).unwrap_or_default();
+		let Some(deploy) = self.deploy_dir(idx) else {
+			let path = installation.join(&app.kind).join(&app.id).join(&app.arch).join(&app.branch);
+			check.total = 1;
+			check.missing.push((path, text::NO_SUCH_FILE.to_string()));
+			return Ok(check);
+		};
+		if !deploy.join("metadata").exists() {
+			check.missing.push((deploy.join("metadata"), text::NO_SUCH_FILE.to_string()));
+		}
+		// the deploy dir is a checkout of the commit (`.../BRANCH/COMMIT`)
+		let commit = deploy.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+		let repo = installation.join(flatpak_strings::REPO_DIR);
+		for (kind, size, path) in commit_files(&repo, commit, "/files")? {
+			check.total += 1;
+			let path = deploy.join(path.trim_start_matches('/'));
+			let Ok(meta) = fs::symlink_metadata(&path) else {
+				check.missing.push((path, text::NO_SUCH_FILE.to_string()));
+				continue;
+			};
+			let file_type = meta.file_type();
+			let same_type = match kind {
+				'd' => file_type.is_dir(),
+				'l' => file_type.is_symlink(),
+				_ => file_type.is_file(),
+			};
+			if !same_type {
+				check.altered.push((path, text::TYPE_MISMATCH.to_string()));
+			} else if kind == '-' && meta.len() != size {
+				check.altered.push((path, text::SIZE_MISMATCH.to_string()));
 			}
-		}//for entry
+		}// for file
+		// `deploy/export/...` is linked into `installation/exports/...`
+		let export = deploy.join("export");
+		let exports_dir = app.exports_dir().unwrap_or_default();
+		for (path, is_dir) in Self::rec_file_explorer(&export).unwrap_or_default() {
+			if is_dir {
+				continue;
+			}
+			check.total += 1;
+			if !exports_dir.join(&path).exists() {
+				check.missing.push((exports_dir.join(&path), text::NO_SUCH_FILE.to_string()));
+			}
+		}
+		Ok(check)
+	}
+
+	/// searches for installed extensions that belong to an app (eg. `.Locale` or `.Debug`)  
+	/// returns a vector of indexes (for self.apps)
+	pub fn get_related(&self, idx: usize) -> Vec<usize> {
+		let app = &self.apps[idx];
+		self.apps.iter()
+			.enumerate()
+			.filter(|(_, other)| {
+				other.arch == app.arch && other.branch == app.branch && other.installation == app.installation
+					&& other.id.strip_prefix(app.id.as_str())
+						.and_then(|rest| rest.strip_prefix('.'))
+						.is_some_and(|suffix| flatpak_strings::RELATED_SUFFIXES.contains(&suffix))
+			})
+			.map(|(i, _)| i)
+			.collect()
+	}
+
+	/// get the installed apps that have an update (in every installation that is used)  
+	/// returns tuples of (index (for self.apps), new version)
+	pub fn get_updates(&self) -> Result<Vec<(usize, String)>, PacpakError> {
+		let mut installations: Vec<String> = self.apps.iter().map(|app| app.installation_arg()).collect();
+		installations.sort();
+		installations.dedup();
+
+		let mut out = Vec::new();
+		for arg in &installations {
+			let updates = flatpak_output(&["remote-ls", "--updates", arg, "--columns=application,arch,branch,version"])?;
+			for line in updates.lines() {
+				let columns: Vec<&str> = line.split('\t').collect();
+				if columns.len() < 4 {
+					return Err(PacpakError::Parse(format!("'flatpak remote-ls' (too few columns): {}", line)));
+				}
+				let index = self.apps.iter().position(|app| {
+					app.installation_arg() == *arg && app.id == columns[0] && app.arch == columns[1] && app.branch == columns[2]
+				});
+				if let Some(index) = index {
+					out.push((index, columns[3].to_string()));
+				}
+			}//for line
+		}
 		Ok(out)
 	}
 
 	// ====== OTHER FUNCTIONS ======
+
+	/// fetch the changes of refs from the flatpak history (of the selected installation, the oldest first)
+	pub fn history(&self) -> Result<Vec<HistoryEntry>, PacpakError> {
+		let scope = self.scope_args();
+		let mut args = vec!["history", "--columns=time,change,ref,installation,remote,commit,old-commit,version"];
+		args.extend(scope.iter().map(String::as_str));
+		Ok(parse_history(&flatpak_output(&args)?))
+	}
+
+	/// get the names of the configured remotes (of the selected installation)
+	pub fn get_remotes(&self) -> Result<Vec<String>, PacpakError> {
+		let scope = self.scope_args();
+		let mut args = vec!["remotes", "--columns=name"];
+		args.extend(scope.iter().map(String::as_str));
+		let mut remotes: Vec<String> = flatpak_output(&args)?
+			.lines()
+			.map(|line| line.trim().to_string())
+			.filter(|line| !line.is_empty())
+			.collect();
+		// a remote can be configured in multiple installations
+		remotes.sort();
+		remotes.dedup();
+		Ok(remotes)
+	}
+
+	/// list all apps and runtimes of a remote
+	pub fn remote_list(&self, remote: &str) -> Result<Vec<FlatpakApp>, PacpakError> {
+		let scope = self.scope_args();
+		let mut args = vec!["remote-ls", "--columns=name,application,arch,branch,version,description"];
+		args.extend(scope.iter().map(String::as_str));
+		args.push(remote);
+		let list_str = flatpak_output(&args)?;
+
+		let mut out = Vec::new();
+		for line in list_str.lines() {
+			let columns: Vec<&str> = line.split('\t').collect();
+			if columns.len() < 6 {
+				return Err(PacpakError::Parse(format!("'flatpak remote-ls' (too few columns): {}", line)));
+			}
+			let mut app = FlatpakApp {
+				name: columns[0].into(),
+				id: columns[1].into(),
+				arch: columns[2].into(),
+				branch: columns[3].into(),
+				version: columns[4].into(),
+				description: columns[5].into(),
+				origin: remote.to_string(),
+				..Default::default()
+			};
+			app.extid = format!("{}/{}/{}", app.id, app.arch, app.branch);
+			out.push(app);
+		}//for line
+		Ok(out)
+	}
+
+	/// test if an app (eg. from a remote) is installed (same id, arch and branch)
+	pub fn is_installed(&self, app: &FlatpakApp) -> bool {
+		self.apps.iter().any(|a| a.id == app.id && a.branch == app.branch && (app.arch.is_empty() || a.arch == app.arch))
+	}
+
+	/// get detailed infos about a (not necessarily installed) app from a remote  
+	/// (`target` is a ref like `id`, `id//branch` or `id/arch/branch`)
+	pub fn remote_info(&self, remote: &str, target: &str, arch: Option<&str>) -> Result<FlatpakApp, PacpakError> {
+		let scope = self.scope_args();
+		let arch_arg = arch.map(|arch| format!("--arch={}", arch));
+		let mut args = vec!["remote-info"];
+		args.extend(scope.iter().map(String::as_str));
+		args.extend(arch_arg.as_deref());
+		args.extend([remote, target]);
+		let info_str = flatpak_output(&args)?;
+
+		let mut app = FlatpakApp::default();
+		parse_info(&mut app, &info_str);
+		app.origin = remote.to_string();
+		app.extid = format!("{}/{}/{}", app.id, app.arch, app.branch);
+		Ok(app)
+	}
+	
+	/// get the commits of an app from the log of a remote (the newest first)  
+	/// (`target` is a ref like `id`, `id//branch` or `app/id/arch/branch`)
+	pub fn remote_log(&self, remote: &str, target: &str, arch: Option<&str>) -> Result<Vec<FlatpakApp>, PacpakError> {
+		let scope = self.scope_args();
+		let arch_arg = arch.map(|arch| format!("--arch={}", arch));
+		let mut args = vec!["remote-info", "--log"];
+		args.extend(scope.iter().map(String::as_str));
+		args.extend(arch_arg.as_deref());
+		args.extend([remote, target]);
+		let info_str = flatpak_output(&args)?;
+
+		let mut commits = parse_log(&info_str);
+		for app in &mut commits {
+			app.origin = remote.to_string();
+		}
+		Ok(commits)
+	}
 	
 	/// search for flatpaks (including not installed)
 	/// returns a vector of results
-	pub fn search(self: &FlatpakMeta, input: Vec<&str>) -> io::Result<Vec<FlatpakApp>> {
-		let mut args = vec!["search".to_string(), "--columns=name,application,branch,version,remotes,description,application".to_string()];
-		args.extend(input.iter().map(|s| s.to_string()));
-		let flatpak_search_raw = Command::new("flatpak")
-			.args(&args)
-			.output()?;
-		if !flatpak_search_raw.status.success() {
-			return Err(io::Error::new(io::ErrorKind::Other, "command: 'flatpak search' failed")); //dev
-		}
-		let search_str: String = String::from_utf8_lossy(&flatpak_search_raw.stdout).into();
+	pub fn search(self: &FlatpakMeta, input: Vec<&str>) -> Result<Vec<FlatpakApp>, PacpakError> {
+		let scope = self.scope_args();
+		let mut args = vec!["search", "--columns=name,application,branch,version,remotes,description,application"];
+		args.extend(scope.iter().map(String::as_str));
+		args.extend(input);
+		let search_str: String = flatpak_output(&args)?;
 
 		let mut results : Vec<FlatpakApp> = vec![];
 		if search_str == format!("{}\n", flatpak_strings::SEARCH_NO_RESULTS) {
@@ -390,16 +1175,197 @@ impl FlatpakMeta {
 					results.push(app);
 				}
 				else {
-					//dev failed
-					eprintln!("error stuff: app has to few columns");
-					println!("{}", line);
+					return Err(PacpakError::Parse(format!("'flatpak search' (too few columns): {}", line)));
 				}
 			}//for line
 		}//if search_str
 
-
-		//println!("{}", search_str);	//dev
 		Ok(results)
 	}
 }
 
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// output of `flatpak repair --dry-run` (stdout and stderr)
+	const REPAIR_OUTPUT: &str = "\
+Working on the system installation at /var/lib/flatpak
+Privileges are required to make changes; assuming --dry-run
+[1/3] Verifying flathub:runtime/org.freedesktop.Platform.GL.default/x86_64/23.08…
+[2/3] Verifying flathub:app/org.gimp.GIMP/x86_64/stable…
+Object missing: 3b2e4d0c8a1f6e5d9c7b0a2f4e6d8c1b3a5f7e9d0c2b4a6f8e1d3c5b7a9f0e2d.file
+Object invalid: 0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0.dirtree
+Dry run: Deleting ref flathub:app/org.gimp.GIMP/x86_64/stable due to missing objects
+[3/3] Verifying flathub:runtime/org.gnome.Platform/x86_64/46…
+Checking remotes...
+Pruning objects
+Erasing .removed
+";
+
+	#[test]
+	fn matches_globs() {
+		let table = [
+			("org.gimp.GIMP", "org.gimp.GIMP", true),
+			("org.gimp.*", "org.gimp.GIMP", true),
+			("org.gimp.*", "org.gimp", false),
+			("*", "", true),
+			("*.Locale", "org.gimp.GIMP.Locale", true),
+			("*.Locale", "org.gimp.GIMP", false),
+			("org.gimp.GIM?", "org.gimp.GIMP", true),
+			("org.gimp.GIM?", "org.gimp.GIM", false),
+			("org.*.Platform", "org.gnome.Sdk.Platform", true),
+			("org.*.Platform", "org.gnome.Platform.Locale", false),
+			("*a*b", "xaxxab", true),
+			("org.gimp.gimp", "org.gimp.GIMP", false),
+		];
+		for (pattern, text, expected) in table {
+			assert_eq!(glob_match(pattern, text), expected, "{} ~ {}", pattern, text);
+		}
+	}
+
+	#[test]
+	fn matches_partial_refs() {
+		let gimp = "app/org.gimp.GIMP/x86_64/stable";
+		let platform = "runtime/org.gnome.Platform/x86_64/46";
+		let table = [
+			("org.gimp.GIMP", gimp, true),
+			("org.gimp", gimp, false),
+			("org.gimp.*", gimp, true),
+			("org.gimp.GIMP/x86_64", gimp, true),
+			("org.gimp.GIMP/aarch64", gimp, false),
+			("org.gimp.GIMP//stable", gimp, true),
+			("org.gimp.GIMP//beta", gimp, false),
+			("org.gimp.GIMP/x86_64/stable", gimp, true),
+			("app/org.gimp.GIMP", gimp, true),
+			("runtime/org.gimp.GIMP", gimp, false),
+			("runtime/org.gnome.*//4?", platform, true),
+			("app/org.gnome.Platform", platform, false),
+			("org.gnome.Platform", "org.gnome.Platform/x86_64/46", true),
+			("org.gimp.GIMP", "app/org.gimp.GIMP.Locale/x86_64/stable", false),
+		];
+		for (pattern, flatpak_ref, expected) in table {
+			assert_eq!(ref_matches(pattern, flatpak_ref), expected, "{} ~ {}", pattern, flatpak_ref);
+		}
+	}
+
+	#[test]
+	fn parses_repair_output() {
+		let problems = parse_repair(REPAIR_OUTPUT);
+		assert_eq!(problems.len(), 1);
+		let gimp = &problems["app/org.gimp.GIMP/x86_64/stable"];
+		assert_eq!(gimp.len(), 2);
+		assert!(gimp[0].ends_with(".file") && gimp[1].ends_with(".dirtree"));
+		assert!(parse_repair("[1/1] Verifying flathub:app/org.gimp.GIMP/x86_64/stable…\nPruning objects\n").is_empty());
+	}
+
+	#[test]
+	fn parses_translated_repair_output() {
+		let output = "\
+[1/2] Überprüfen von flathub:app/org.gimp.GIMP/x86_64/stable ...
+[2/2] Überprüfen von flathub:runtime/org.gnome.Platform/x86_64/46 ...
+Objekt fehlt: 3b2e4d0c8a1f6e5d9c7b0a2f4e6d8c1b3a5f7e9d0c2b4a6f8e1d3c5b7a9f0e2d.file
+";
+		let problems = parse_repair(output);
+		assert_eq!(problems.keys().collect::<Vec<_>>(), ["runtime/org.gnome.Platform/x86_64/46"]);
+		// problems before the first ref belong to no ref
+		let problems = parse_repair("Objet manquant : 3b2e4d0c8a1f6e5d9c7b0a2f4e6d8c1b3a5f7e9d0c2b4a6f8e1d3c5b7a9f0e2d.commit\n");
+		assert_eq!(problems[""].len(), 1);
+	}
+
+	#[test]
+	fn parses_ostree_ls() {
+		let output = "\
+d00755 0 0      0 /files
+d00755 0 0      0 /files/bin
+-00755 0 0  12345 /files/bin/gimp-2.10
+l00777 0 0      0 /files/bin/gimp -> gimp-2.10
+-00644 0 0      7 /files/share/a file with spaces
+";
+		assert_eq!(parse_ostree_ls(output), [
+			('d', 0, "/files".to_string()),
+			('d', 0, "/files/bin".to_string()),
+			('-', 12345, "/files/bin/gimp-2.10".to_string()),
+			('l', 0, "/files/bin/gimp".to_string()),
+			('-', 7, "/files/share/a file with spaces".to_string()),
+		]);
+		assert!(parse_ostree_ls("error: No such file\n").is_empty());
+	}
+
+	#[test]
+	fn parses_history() {
+		let output = "\
+Jun 01 10:00:00\tdeploy install\tapp/org.gimp.GIMP/x86_64/stable\tsystem\tflathub\taaaaaaaaaaaa\t\t2.10.36
+Jun 05 10:00:00\tpull\t\tsystem\tflathub\t\t\t
+Jun 10 12:00:00\tdeploy update\tapp/org.gimp.GIMP/x86_64/stable\tsystem\tflathub\tbbbbbbbbbbbb\taaaaaaaaaaaa\t2.10.38
+Jun 11 08:00:00\tuninstall\truntime/org.gnome.Platform/x86_64/45\tuser\tflathub\t\tcccccccccccc\t
+too\tfew\tcolumns
+";
+		let history = parse_history(output);
+		assert_eq!(history.len(), 3);
+		assert_eq!(history[0].change, "deploy install");
+		assert_eq!(history[0].old_commit, "");
+		let update = &history[1];
+		assert_eq!((update.time.as_str(), update.flatpak_ref.as_str()), ("Jun 10 12:00:00", "app/org.gimp.GIMP/x86_64/stable"));
+		assert_eq!((update.commit.as_str(), update.old_commit.as_str(), update.version.as_str()), ("bbbbbbbbbbbb", "aaaaaaaaaaaa", "2.10.38"));
+		assert_eq!((history[2].installation.as_str(), history[2].commit.as_str()), ("user", ""));
+		assert!(parse_history("").is_empty());
+	}
+
+	#[test]
+	fn parses_log() {
+		let output = "
+GNU Image Manipulation Program - Create images and edit photographs
+
+          ID: org.gimp.GIMP
+         Ref: app/org.gimp.GIMP/x86_64/stable
+        Arch: x86_64
+      Branch: stable
+     Version: 2.10.38
+      Commit: cccccccccccc3333
+
+    History:
+
+      Commit: cccccccccccc3333
+     Subject: Update to 2.10.38
+        Date: 2024-06-10 12:00:00 +0000
+
+      Commit: bbbbbbbbbbbb2222
+     Subject: Update to 2.10.36
+        Date: not a date
+";
+		let log = parse_log(output);
+		assert_eq!(log.len(), 2);
+		assert_eq!(log[0].extid, "org.gimp.GIMP/x86_64/stable");
+		assert_eq!((log[0].commit.as_str(), log[0].subject.as_str(), log[0].version.as_str()), ("cccccccccccc3333", "Update to 2.10.38", "2.10.38"));
+		assert_ne!(log[0].build_date, "2024-06-10 12:00:00 +0000");
+		// only the latest commit has a known version
+		assert_eq!((log[1].commit.as_str(), log[1].version.as_str()), ("bbbbbbbbbbbb2222", text::VERSION_UNKOWN));
+		assert_eq!(log[1].build_date, "not a date");
+		// without a log: only the latest commit
+		let latest = parse_log(output.split_once("History:").unwrap().0);
+		assert_eq!(latest.len(), 1);
+		assert_eq!(latest[0].commit, "cccccccccccc3333");
+		assert!(parse_log("").is_empty());
+	}
+
+	#[test]
+	fn finds_the_alternate_system_dir() {
+		assert_eq!(alternate_system_dir(None, None), None);
+		assert_eq!(alternate_system_dir(Some("/mnt"), None), Some(PathBuf::from("/mnt/var/lib/flatpak")));
+		assert_eq!(alternate_system_dir(None, Some("/mnt/var/lib/pacman/")), Some(PathBuf::from("/mnt/var/lib/flatpak")));
+		assert_eq!(alternate_system_dir(Some("/mnt"), Some("/db/pacman")), Some(PathBuf::from("/db/flatpak")));
+	}
+
+	#[test]
+	fn uses_the_scratch_system_dir() {
+		// (without setting the system dir, it is global for the whole test binary)
+		let dir = Path::new("/tmp/scratch/var/lib/flatpak");
+		let command = flatpak_command_in(Some(dir));
+		let env_dir = command.get_envs().find(|(key, _)| *key == "FLATPAK_SYSTEM_DIR").and_then(|(_, value)| value);
+		assert_eq!(env_dir, Some(dir.as_os_str()));
+		assert_eq!(flatpak_command_in(None).get_envs().count(), 0);
+		assert_eq!(system_installation_path(Some(dir)), dir);
+	}
+}
@@ -3,23 +3,42 @@
 
 // main.rs
 
-use clap::Parser;		// cli input parser
 use std::process::{exit, ExitStatus}; // exit with an error
 use colored::{Colorize, control};	// format output strings (for the terminal)
-//dev; needed
 use std::env;			// fetch the environment args
 use std::process::{Command, Stdio};
-use std::io::Read;		// pipe the output of a command
+use std::io::{self, Read, Write};	// pipe the output of a command
 // file path stuff
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::collections::HashMap;
+use chrono::Local;
 
 // handling of cli args in cli.rs
 mod cli;
 use cli::Cli;
+// settings (and the config file) in config.rs
+mod config;
+use config::Config;
 // flatpak integration in flatpak.rs
 mod flatpak;
 use flatpak::{FlatpakMeta, FlatpakApp, flatpak_strings};
+// error type in error.rs
+mod error;
+use error::PacpakError;
+// local flatpak files in bundle.rs
+mod bundle;
+use bundle::LocalFlatpak;
+// AppStream catalogs of the remotes in appstream.rs
+mod appstream;
+// databases built by pacpak in cache.rs
+mod cache;
+use cache::FileEntry;
+// version comparison in version.rs
+mod version;
+// search terms in search.rs
+mod search;
+use search::Matcher;
 
 
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
@@ -31,26 +50,13 @@ mod exit_status {
 	pub const SUCCESS: i32 = 0;
 	/// a command returns no results
 	pub const NOT_FOUND: i32 = 1;
+	/// a failure pacman would report (pacman returns 1 for most errors)
+	pub const FAILURE: i32 = 1;
+	/// a needed program (flatpak/pacman) could not be executed
+	pub const MISSING: i32 = 127;
 	/// an unkown failure within this or within a called program occurs
 	pub const ERROR: i32 = 255;
-
-}
-
-/// store (user) settings
-struct Config {
-	wrap_pacman: bool,
-	color:		bool,
 }
-/// standart values for the settings
-impl Default for Config {
-	fn default() -> Self {
-		Self {
-			wrap_pacman: true,
-			color:		true,
-		}
-	}
-}
-
 
 /// message strings for eg. -Qi fields, --help (and more)
 mod text {
@@ -58,6 +64,7 @@ mod text {
 
 	pub const NOT_IMPLEMENTED: &str = "[not implemented]";
 	pub const NONE: &str = "None";
+	pub const UNKNOWN: &str = "?";
 	pub const INSTALL_REASON_EXP: &str = "Explicitly installed";
 	pub const INSTALL_REASON_DEP: &str = "Installed as a dependency for another package";
 	pub const INSTALLED_MARKER: &str = "[installed]";
@@ -66,7 +73,49 @@ mod text {
 	pub const WARNING_PREFIX:&str = "warning:";
 	pub const NO_TARGETS:&str = "no targets specified (use -h for help)";
 	pub const NO_PACMAN_PACKAGE:&str = "no pacman target found";
-	///
+	pub const DID_YOU_MEAN:&str = "did you mean";
+	pub const NO_OWNER:&str = "No package owns";
+	pub const UP_TO_DATE:&str = "is up to date -- skipping";
+	pub const PROCEED_INSTALL:&str = "Proceed with installation?";
+	pub const NOTHING_TO_DO:&str = "there is nothing to do";
+	pub const USING_CACHED_LIST:&str = "failed to list the remote, using the cached list";
+	pub const IGNORING_UPGRADE:&str = "ignoring package upgrade";
+	pub const IGNORED_INSTALL:&str = "is in IgnorePkg/IgnoreRelated. Install anyway?";
+	pub const HOLD_PKG:&str = "is designated as a HoldPkg.";
+	pub const HOLD_PKG_CONTINUE:&str = "HoldPkg was found in target list. Do you want to continue?";
+	pub const REASON_SET_EXP:&str = "install reason has been set to 'explicitly installed'";
+	pub const REASON_SET_DEP:&str = "install reason has been set to 'installed as dependency'";
+	pub const APP_ALWAYS_EXPLICIT:&str = "flatpak apps are always explicitly installed -- skipping";
+	pub const NO_FLATPAK_ERRORS:&str = "No flatpak installation errors have been found!";
+	pub const NO_PREVIOUS_COMMIT:&str = "no previous commit found in the flatpak history for";
+	pub const ROLLBACK:&str = "Rolling back";
+	pub const DOWNGRADE:&str = "Downgrading";
+	pub const COMMITS_OF:&str = "Commits of";
+	pub const ENTER_NUMBER:&str = "Enter a number";
+	pub const INVALID_NUMBER:&str = "invalid number:";
+	pub const NO_OLDER_COMMIT:&str = "no older commit found in the log of the remote for";
+	pub const NO_COMMIT:&str = "no commit found in the log of the remote for";
+	pub const EMPTY_COMMIT:&str = "option '--commit' requires a commit or version";
+	pub const ALREADY_DEPLOYED:&str = "is already at this commit -- skipping";
+	pub const CHANGELOG_FOR:&str = "Changelog for";
+	pub const NO_CHANGELOG:&str = "no changelog available for";
+	/// the number of commits (of the remote log) shown by -Qc
+	pub const CHANGELOG_COMMITS: usize = 10;
+	pub const IGNORED_MARKER: &str = "[ignored]";
+	pub const CLEAN_KEEP:&str = "Flatpaks to keep:";
+	pub const CLEAN_KEEP_ALL:&str = "  All apps, pinned runtimes and the runtimes used by apps";
+	pub const CLEAN_INSTALLATION:&str = "Flatpak installation:";
+	pub const CLEAN_UNUSED:&str = "Do you want to remove all unused flatpaks?";
+	pub const CLEANING_UNUSED:&str = "removing unused flatpaks...";
+	pub const CLEAN_REPO:&str = "Do you want to remove ALL unused objects from the repository?";
+	pub const CLEANING_REPO:&str = "removing all unused objects from the repository...";
+	pub const CLEAN_RECLAIMED:&str = "Reclaimed space:";
+	pub const SYNC_FILES:&str = "Synchronizing flatpak files database...";
+	pub const SYNC_SEARCH:&str = "Synchronizing flatpak search index...";
+	pub const NO_FILES_DB:&str = "no flatpak files database found (use pacpak -Fy)";
+	pub const NO_SEARCH_DB:&str = "no flatpak search index found (use pacpak -Sy)";
+	pub const NO_CACHED_LIST:&str = "no cached list of the remote found (use pacpak -Sl without --offline)";
+	/// header for the flatpak part of the removal output
 	pub const UNINSTALL_SPACER:&str = "FLATPAKs:";
 	/// Identation for the version display
 	pub const VERSION_IDENTATION: &str = "                       ";
@@ -80,6 +129,9 @@ mod text {
 			{-S, --sync}
 			{-R, --remove}
 			{-h --help}
+			{--history}
+			{--rollback}
+			{--downgrade}
 			{-V --version}
 			
 			(or other pacman operations)
@@ -89,25 +141,289 @@ mod text {
 
 
 /// output the given app in the format:
-///		`id (name) version (branch)`
+///     `id (name) version (branch) [installation]`
 /// (similar to `pacman -Q`)
 fn print_app_short(app: &FlatpakApp) {
-	println!("{} ({}) {} ({})", app.id.bold(), app.name, app.version.bold().green(), app.branch);
+	println!("{} ({}) {} ({}) [{}]", app.id.bold(), app.name, app.version.bold().green(), app.branch, app.installation);
 }
 
 /// output the given app in the format:
-///		`remote/id (name) version (branch)`
+///     `remote/id (name) version (branch)`
 /// (similar to `pacman -Ss`)
 fn print_app_long(app: &FlatpakApp, installed: bool) {
+	println!("{}", format_app_long(app, installed));
+}
+
+/// format the given app like print_app_long() (two lines)
+fn format_app_long(app: &FlatpakApp, installed: bool) -> String {
 	let installed_str: &str = if installed {
 		&text::INSTALLED_MARKER.cyan().bold().to_string()
 	} else { "" };
 	
-	println!("{}{}{} ({}) {} ({}) {}", app.origin.magenta().bold(), "/".bold(), app.id.bold(), app.name, app.version.bold().green(), app.branch, installed_str);
-	println!("{}{}", text::DESCRIPTION_IDENTATION, app.description);
+	format!("{}{}{} ({}) {} ({}) {}\n{}{}", app.origin.magenta().bold(), "/".bold(), app.id.bold(), app.name, app.version.bold().green(), app.branch, installed_str,
+		text::DESCRIPTION_IDENTATION, app.description)
+}
+
+/// format the given app in the format:
+///     `remote/id//branch version`
+/// (like a line of `pacman -Q`, used for the merged output)
+fn format_app_merged(app: &FlatpakApp, quiet: bool) -> String {
+	let name = format!("{}{}{}//{}", app.origin.magenta().bold(), "/".bold(), app.id.bold(), app.branch.bold());
+	if quiet {
+		name
+	} else {
+		format!("{} {}", name, app.version.bold().green())
+	}
+}
+
+/// the sort key of an app in the merged output  
+/// (the last part of its id, eg. `gimp` for `org.gimp.GIMP`, so it is sorted in between pacman packages)
+fn merged_key(app: &FlatpakApp) -> String {
+	let name = app.id.rsplit('.').next().unwrap_or(&app.id);
+	format!("{} {}", name.to_lowercase(), app.id.to_lowercase())
+}
+
+/// remove the errors of pacman about targets that were not found, but are flatpaks  
+/// (eg. `error: package 'gimp' was not found`)
+fn without_not_found(stderr: &str, targets: &[&str]) -> String {
+	stderr.lines()
+		.filter(|line| !targets.iter().any(|target| line.contains(&format!("'{}'", target))))
+		.map(|line| format!("{}\n", line))
+		.collect()
+}
+
+/// split the (uncolored) output of `pacman -Q` or `pacman -Qs` into entries  
+/// return tuples of (sort key, colored entry)
+fn pacman_entries(stdout: &str) -> Vec<(String, String)> {
+	let mut out: Vec<(String, String)> = Vec::new();
+	for line in stdout.lines() {
+		if line.starts_with(' ') {
+			// description of the previous entry (with -Qs)
+			if let Some((_, entry)) = out.last_mut() {
+				entry.push('\n');
+				entry.push_str(line);
+			}
+			continue;
+		}
+		let mut words = line.splitn(3, ' ');
+		let full_name = words.next().unwrap_or_default();
+		let (repo, name) = match full_name.split_once('/') {
+			Some((repo, name)) => (format!("{}{}", repo.magenta().bold(), "/".bold()), name),
+			None => (String::new(), full_name),
+		};
+		let mut entry = format!("{}{}", repo, name.bold());
+		if let Some(version) = words.next() {
+			entry = format!("{} {}", entry, version.bold().green());
+		}
+		if let Some(rest) = words.next() {
+			entry = format!("{} {}", entry, rest);
+		}
+		out.push((name.to_lowercase(), entry));
+	}//for line
+	out
+}
+
+/// output the entries of pacman and flatpak sorted by their keys (as one list)
+fn print_merged(mut entries: Vec<(String, String)>) {
+	entries.sort_by(|a, b| a.0.cmp(&b.0));
+	for (_, entry) in &entries {
+		println!("{}", entry);
+	}
+}
+
+/// output the local flatpak files that will be installed  
+/// (similar to the package list of pacman with `VerbosePkgLists`)
+fn print_local_plan(files: &[LocalFlatpak]) {
+	let header = [format!("Flatpak ({})", files.len()), "Remote".to_string(), "Installed Size".to_string()];
+	let rows: Vec<[String; 3]> = files.iter()
+		.map(|f| [
+			f.flatpak_ref.clone(),
+			f.remote.clone(),
+			f.installed_size.map(format_size).unwrap_or(text::UNKNOWN.to_string()),
+		])
+		.collect();
+	let width = |col: usize| rows.iter().map(|row| row[col].len()).chain([header[col].len()]).max().unwrap_or_default();
+	let (w0, w1) = (width(0), width(1));
+	println!("{}", format!("{:w0$}  {:w1$}  {}", header[0], header[1], header[2]).bold());
+	for row in &rows {
+		println!("{:w0$}  {:w1$}  {:>w2$}", row[0], row[1], row[2], w2 = header[2].len());
+	}
+	println!();
+	let total: u64 = files.iter().filter_map(|f| f.installed_size).sum();
+	println!("{} {}", "Total Installed Size:".bold(), format_size(total));
+	println!();
+}
+
+/// output an entry of the flatpak history in the format:
+///     `[time] [installation] change remote:ref version (old commit -> new commit)`
+/// (similar to a line of the pacman log)
+fn print_history_entry(entry: &flatpak::HistoryEntry) {
+	let commits = match (entry.old_commit.is_empty(), entry.commit.is_empty()) {
+		(true, true) => String::new(),
+		(true, false) => format!("({})", short_commit(&entry.commit)),
+		(false, _) => format!("({} -> {})", short_commit(&entry.old_commit), short_commit(&entry.commit)),
+	};
+	// like flatpak refspecs: `remote:ref`
+	let refspec = if entry.remote.is_empty() { entry.flatpak_ref.clone() } else { format!("{}:{}", entry.remote, entry.flatpak_ref) };
+	println!("[{}] [{}] {} {} {} {}", entry.time, entry.installation, entry.change, refspec.bold(), entry.version.green().bold(), commits);
+}
+
+/// format a commit of a remote log in the format:
+///     `commit date subject [installed]`
+fn format_log_commit(commit: &FlatpakApp, installed: bool) -> String {
+	let marker = if installed { format!(" {}", text::INSTALLED_MARKER.cyan().bold()) } else { String::new() };
+	format!("{} {} {}{}", short_commit(&commit.commit).bold(), commit.build_date, commit.subject, marker)
+}
+
+/// output the changelog of an installed flatpak (like `pacman -Qc`):
+/// the release notes from the AppStream catalog of its remote and the latest commits from the log of the remote  
+/// (update: only the changes between the installed and the given new version (if they are known); offline: without the log)  
+/// returns false if there is no changelog
+fn print_changelog(flatpak: &mut FlatpakMeta, catalogs: &mut appstream::Catalogs, index: usize, update: Option<&str>, offline: bool) -> Result<bool, PacpakError> {
+	flatpak.get_kind(index)?;
+	flatpak.get_app_info_full(index)?;
+	let app = &flatpak.apps[index];
+	let known = |version: &str| !version.is_empty() && version != text::UNKNOWN;
+	let component = catalogs.find_component(&app.origin, &app.id)?;
+	let releases: Vec<&appstream::Release> = component.iter()
+		.flat_map(|component| &component.releases)
+		.filter(|release| update.is_none_or(|new_version| {
+			(!known(&app.version) || version::vercmp(&release.version, &app.version).is_gt())
+				&& (!known(new_version) || version::vercmp(&release.version, new_version).is_le())
+		}))
+		.collect();
+	let commits = if offline {
+		Vec::new()
+	} else {
+		// the changelog is still useful without the log (eg. if the remote can not be reached)
+		flatpak.remote_log(&app.origin, &app.full_ref(), None).unwrap_or_else(|e| {
+			eprintln!("{}", e);
+			Vec::new()
+		})
+	};
+	let installed = commits.iter().position(|commit| same_commit(&commit.commit, &app.commit));
+	// updates: only the commits after the installed one
+	let shown = match update {
+		Some(_) => installed.unwrap_or(commits.len()),
+		None => commits.len(),
+	}.min(text::CHANGELOG_COMMITS);
+	if releases.is_empty() && shown == 0 {
+		eprintln!("{} {} '{}'", text::ERROR_PREFIX.red().bold(), text::NO_CHANGELOG, app.extid);
+		return Ok(false);
+	}
+
+	println!("{} {}:", text::CHANGELOG_FOR.bold(), app.extid.bold());
+	for release in releases {
+		let date = if release.date.is_empty() { String::new() } else { format!(" ({})", release.date) };
+		println!("{}{}", release.version.green().bold(), date);
+		for note in &release.notes {
+			println!("{}{}", text::DESCRIPTION_IDENTATION, note);
+		}
+	}
+	if shown > 0 {
+		println!("{} {}:{}:", text::COMMITS_OF.bold(), app.origin, app.full_ref());
+		for (i, commit) in commits.iter().enumerate().take(shown) {
+			println!("{}{}", text::DESCRIPTION_IDENTATION, format_log_commit(commit, installed == Some(i)));
+		}
+	}
+	println!();
+	Ok(true)
 }
 
-///	output the given app similar to `pacman -Qi`
+/// test if two commits are the same (one can be shortened)
+fn same_commit(a: &str, b: &str) -> bool {
+	!a.is_empty() && !b.is_empty() && (a.starts_with(b) || b.starts_with(a))
+}
+
+/// update an installed flatpak to a commit of its remote (for --rollback and --downgrade)  
+/// (--mask: and mask it, so it is not upgraded again)  
+/// the change is recorded as a downgrade (for --history)
+fn deploy_commit(app: &FlatpakApp, commit: &str, noconfirm: bool, mask: bool) -> Result<ExitStatus, PacpakError> {
+	let mut flat_args = vec!["update".to_string(), format!("--commit={}", commit), app.installation_arg()];
+	if noconfirm {
+		flat_args.push(flatpak_strings::NONINTERACTIVE.to_string());
+	}
+	flat_args.push(app.full_ref());
+	let status = flatpak_exec(&flat_args)?;
+	if !status.success() {
+		return Ok(status);
+	}
+	// flatpak records it as an update
+	let entry = flatpak::HistoryEntry {
+		time: Local::now().format("%b %d %H:%M:%S").to_string(),
+		flatpak_ref: app.full_ref(),
+		installation: app.installation.clone(),
+		remote: app.origin.clone(),
+		commit: commit.to_string(),
+		old_commit: app.commit.clone(),
+		..Default::default()
+	};
+	if let Err(e) = cache::record_downgrade(&entry) {
+		eprintln!("{}", e);
+	}
+	if mask {
+		// masked refs are not updated (eg. by -Syu) until `flatpak mask --remove`
+		return flatpak_exec(&["mask".to_string(), app.installation_arg(), app.full_ref()]);
+	}
+	Ok(status)
+}
+
+/// ask the user for a number (like pacman: `Enter a number (default=1):`)  
+/// returns the number (1 to max, an empty answer is the default), None if there is no more input
+fn choose_number(max: usize, default: usize) -> Option<usize> {
+	loop {
+		print!("{} ", format!("{} (default={}):", text::ENTER_NUMBER, default).bold());
+		let _ = io::stdout().flush();
+		let mut answer = String::new();
+		match io::stdin().read_line(&mut answer) {
+			Ok(0) | Err(_) => return None,
+			Ok(_) => {},
+		}
+		let answer = answer.trim();
+		if answer.is_empty() {
+			return Some(default);
+		}
+		match answer.parse::<usize>() {
+			Ok(number) if (1..=max).contains(&number) => return Some(number),
+			_ => eprintln!("{} {} {}", text::ERROR_PREFIX.red().bold(), text::INVALID_NUMBER, answer),
+		}
+	}
+}
+
+/// shorten a commit (like flatpak does, eg. for `flatpak info`)
+fn short_commit(commit: &str) -> &str {
+	commit.get(..12).unwrap_or(commit)
+}
+
+/// format a size in bytes like pacman (eg. `12.34 MiB`)
+fn format_size(bytes: u64) -> String {
+	let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+	let mut size = bytes as f64;
+	let mut unit = 0;
+	while size >= 1024.0 && unit < units.len() - 1 {
+		size /= 1024.0;
+		unit += 1;
+	}
+	format!("{:.2} {}", size, units[unit])
+}
+
+/// ask the user a yes/no question (like pacman: `:: question? [Y/n]`)  
+/// return true if the user agreed (an empty answer is the default)
+fn confirm(question: &str, default: bool) -> bool {
+	let choices = if default { "[Y/n]" } else { "[y/N]" };
+	print!("{} {} {} ", "::".blue().bold(), question.bold(), choices);
+	let _ = io::stdout().flush();
+	let mut answer = String::new();
+	if io::stdin().read_line(&mut answer).is_err() {
+		return false;
+	}
+	match answer.trim().to_lowercase().as_str() {
+		"" => default,
+		answer => answer == "y" || answer == "yes",
+	}
+}
+
+/// output the given app similar to `pacman -Qi`
 fn print_app_info(app: &FlatpakApp) {
 	let mut name = String::new();
 	if !app.name.is_empty() {
@@ -117,6 +433,7 @@ fn print_app_info(app: &FlatpakApp) {
 	println!("{} {} ({})", "Version		:".bold(),app.version, app.branch);
 	println!("{} {}", "Description	:".bold(),app.description);
 	println!("{} {}", "Architecture	:".bold(),app.arch);
+	println!("{} {}", "Installation	:".bold(),app.installation);
 	println!("{} {}", "URL		:".bold(),app.url);
 	println!("{} {}", "Licenses	:".bold(),app.license);
 	println!("{} {}", "Groups		:".bold(),app.collection);
@@ -132,7 +449,8 @@ fn print_app_info(app: &FlatpakApp) {
 	println!("{} {}", "Build Date	:".bold(),app.build_date);
 	println!("{} {}", "Install Date	:".bold(),app.install_date);
 	
-	if app.runtime.is_empty() {
+	// runtimes are explicitly installed if they are pinned
+	if app.runtime.is_empty() && !app.pinned {
 		println!("{} {}", "Install Reason	:".bold(), text::INSTALL_REASON_DEP);
 	} else {
 		println!("{} {}", "Install Reason	:".bold(), text::INSTALL_REASON_EXP);
@@ -143,117 +461,415 @@ fn print_app_info(app: &FlatpakApp) {
 	println!();
 }
 
-/// find the installed flatpak package that owns the target file  
-/// return its index (in flatpak.apps) or -1 if none was found
-fn is_owned_by(flatpak: &mut FlatpakMeta, target: &str) -> std::io::Result<isize> {
-	let target_path = PathBuf::from(&target);
-	for index in 0..flatpak.apps.len() {
-		if flatpak.apps[index].location.is_empty() {
-			let _ = flatpak.get_location(index);
+/// output the given (remote) app similar to `pacman -Si`
+fn print_app_sync_info(app: &FlatpakApp) {
+	println!("{} {}", "Repository	:".bold(), app.origin);
+	println!("{} {} ({})", "Name		:".bold(), app.id, app.name);
+	println!("{} {} ({})", "Version		:".bold(), app.version, app.branch);
+	println!("{} {}", "Description	:".bold(), app.description);
+	println!("{} {}", "Architecture	:".bold(), app.arch);
+	println!("{} {}", "URL		:".bold(), app.url);
+	println!("{} {}", "Licenses	:".bold(), app.license);
+	println!("{} {}", "Groups		:".bold(), app.collection);
+	println!("{} {}", "Provides	:".bold(), app.provides);
+	println!("{} {}", "Depends On	:".bold(), app.depends);
+	println!("{} {}", "Optional Deps	:".bold(), text::NONE);
+	println!("{} {}", "Conflicts With	:".bold(), text::NONE);
+	println!("{} {}", "Replaces	:".bold(), text::NOT_IMPLEMENTED);
+	println!("{} {}", "Download Size	:".bold(), app.download_size);
+	println!("{} {}", "Installed Size	:".bold(), app.install_size);
+	println!("{} {}", "Packager	:".bold(), app.packager);
+	println!("{} {}", "Build Date	:".bold(), app.build_date);
+	println!("{} {}", "Validated By 	:".bold(), text::NOT_IMPLEMENTED);
+	println!();
+}
+
+/// add a branch to a flatpak target without one (eg. `id` -> `id//branch`)
+fn with_branch(target: &str, branch: Option<&str>) -> String {
+	match branch {
+		Some(branch) if !target.contains('/') => format!("{}//{}", target, branch),
+		_ => target.to_string(),
+	}
+}
+
+/// collect the names for `did you mean` suggestions (the ids of the installed flatpaks and the installed pacman packages)  
+/// with `remote`: the ids of the flatpaks in the search index instead of the pacman packages (pacman suggests its own packages)  
+/// (root_args: see pacman_root_args())
+fn suggestion_names(flatpak: &FlatpakMeta, remote: bool, pacman: bool, root_args: &[String]) -> Vec<String> {
+	let mut names: Vec<String> = flatpak.apps.iter().map(|app| app.id.clone()).collect();
+	if remote {
+		if let Ok(Some(components)) = cache::read_search() {
+			names.extend(components.into_iter().map(|component| component.id));
+		}
+	} else if pacman && let Ok((stdout_pac, _, status_pac)) = pacman_run(&[root_args, &["-Qq".to_string()]].concat()) && status_pac.success() {
+		names.extend(stdout_pac.lines().filter_map(|line| line.split_whitespace().next()).map(String::from));
+	}
+	names.sort();
+	names.dedup();
+	names
+}
+
+/// print the closest names for unknown targets (eg. `:: vlx: did you mean vlc, org.videolan.VLC?`)
+/// (targets that are known names are skipped)
+fn print_suggestions(targets: &[&str], names: &[String]) {
+	for target in targets {
+		let name = target.split('/').next().unwrap_or_default();
+		if names.iter().any(|known| known == name) {
+			continue;
 		}
-		let app_path = PathBuf::from(&flatpak.apps[index].location);
-		let target_path = fs::canonicalize(&target_path)?;
-		let app_path = fs::canonicalize(&app_path)?;
-		if target_path.starts_with(&app_path) {
-			return Ok(index.try_into().unwrap());
+		let found = search::suggest(target, names);
+		if !found.is_empty() {
+			eprintln!("{} {}: {} {}?", "::".blue().bold(), target, text::DID_YOU_MEAN, found.join(", "));
 		}
-	}//for
-	Ok(-1)
+	}
 }
 
+/// the flatpak installations to operate on (the selected one, or the system and user installation) and their paths  
+/// (installations that do not exist are skipped)
+fn flatpak_installations(flatpak: &FlatpakMeta) -> Vec<(String, PathBuf)> {
+	let names: Vec<String> = match &flatpak.installation {
+		Some(installation) => vec![installation.clone()],
+		None => vec!["system".to_string(), "user".to_string()],
+	};
+	names.into_iter()
+		.filter_map(|name| flatpak::installation_path(&name).filter(|path| path.is_dir()).map(|path| (name, path)))
+		.collect()
+}
 
-/// call pacman with the given args (inherit buffers)  
-/// return the exit status
-fn pacman_exec(args: &Vec<String>) -> ExitStatus {
-	Command::new("pacman")
-		.args(args)
-		.stdin(Stdio::inherit())
-		.stdout(Stdio::inherit())
-		.stderr(Stdio::inherit())
-		.status()
-		.expect(&format!("{prefix} failed to execute pacman", prefix = text::ERROR_PREFIX.red().bold()))
+/// the remotes that provide a flatpak (the remote is searched, if it is not given)
+fn flatpak_remotes(flatpak: &FlatpakMeta, id: &str, remote: Option<&String>) -> Result<Vec<String>, PacpakError> {
+	if let Some(remote) = remote {
+		return Ok(vec![remote.clone()]);
+	}
+	Ok(flatpak.search(vec![id])?.iter()
+		.filter(|app| app.id == id)
+		.flat_map(|app| app.origin.split(',').map(String::from).collect::<Vec<String>>())
+		.collect())
 }
 
-/// call pacman with the given args (pipe buffers)  
-/// return a tuple of (stdout, stderr, exit status)
-fn pacman_run(args: &Vec<String>) -> (String, String, ExitStatus) {
-	let mut child = Command::new("pacman")
-		.args(args)
-		.stdin(Stdio::null())
-		.stdout(Stdio::piped())
-		.stderr(Stdio::piped())
-		.spawn()
-		.expect(&format!("{prefix} failed to execute pacman", prefix = text::ERROR_PREFIX.red().bold()));
+/// export downloaded flatpaks (and their runtimes) as `.flatpak` bundles into a dir (for -Sw --cachedir)  
+/// (the file names contain the branch, eg. `org.gnome.Platform-46.flatpak`; -S --cachedir and -U install them)
+fn export_flatpaks(flatpak: &FlatpakMeta, targets: &[String], dir: &str, remote: Option<&String>, arch: Option<&str>) -> Result<ExitStatus, PacpakError> {
+	let installation = flatpak.installation.as_deref().unwrap_or("system");
+	let repo = flatpak::installation_path(installation)
+		.ok_or_else(|| PacpakError::Usage(format!("unknown installation '{}'", installation)))?
+		.join("repo");
+	fs::create_dir_all(dir)?;
+	// (is runtime, id, branch), the branches are the ones of the downloaded refs
+	let mut bundles: Vec<(bool, String, String)> = Vec::new();
+	for target in targets {
+		let id = target.split('/').next().unwrap_or_default();
+		let remotes = flatpak_remotes(flatpak, id, remote)?;
+		let Some(Ok(app)) = remotes.first().map(|remote| flatpak.remote_info(remote, target, arch)) else {
+			return Err(PacpakError::TargetNotFound(target.to_string()));
+		};
+		bundles.push((false, app.id.clone(), app.branch.clone()));
+		// the runtime is given as `id/arch/branch`
+		let mut parts = app.runtime.splitn(3, '/');
+		if let (Some(id), Some(_), Some(branch)) = (parts.next(), parts.next(), parts.next()) {
+			bundles.push((true, id.to_string(), branch.to_string()));
+		}
+	}
+	// apps can share a runtime
+	bundles.sort();
+	bundles.dedup();
+	let mut status = None;
+	for (is_runtime, id, branch) in bundles {
+		let file = Path::new(dir).join(format!("{}-{}.flatpak", id, branch));
+		let mut flat_args = vec!["build-bundle".to_string()];
+		if is_runtime {
+			flat_args.push("--runtime".to_string());
+		}
+		flat_args.extend(arch.map(|arch| format!("--arch={}", arch)));
+		flat_args.extend([repo.display().to_string(), file.display().to_string(), id, branch]);
+		let local_status = flatpak_exec(&flat_args)?;
+		if !local_status.success() {
+			return Ok(local_status);
+		}
+		status = Some(local_status);
+	}
+	status.ok_or(PacpakError::NoTargets)
+}
+
+/// the flatpak args to install a local flatpak (a ref from a sideload repo, a `.flatpakref` file or a bundle)
+fn local_install_args(local: &LocalFlatpak) -> Vec<String> {
+	match &local.sideload_repo {
+		Some(repo) => vec![format!("--sideload-repo={}", repo), local.flatpak_ref.clone()],
+		None => {
+			let source = if local.is_ref_file { "--from" } else { "--bundle" };
+			vec![source.to_string(), local.path.clone()]
+		},
+	}
+}
+
+/// rebuild the databases of pacpak from the AppStream catalogs (files and search index)
+/// (catalogs that can not be read are skipped with an error message)
+fn rebuild_appstream_dbs() -> Result<(), PacpakError> {
+	for e in cache::rebuild_appstream()? {
+		eprintln!("{}", e);
+	}
+	Ok(())
+}
+
+/// refresh the AppStream catalogs of the remotes (`flatpak update --appstream`) and rebuild the databases of pacpak
+fn refresh_appstream(flatpak: &FlatpakMeta, noconfirm: bool) -> Result<(), PacpakError> {
+	let mut flat_args = vec!["update".to_string(), "--appstream".to_string()];
+	if noconfirm {
+		flat_args.push(flatpak_strings::NONINTERACTIVE.to_string());
+	}
+	flat_args.extend(flatpak.scope_args());
+	let local_status = flatpak_exec(&flat_args)?;
+	if !local_status.success() {
+		return Err(PacpakError::CommandFailed { command: "flatpak update --appstream".to_string(), stderr: String::new(), code: local_status.code() });
+	}
+	rebuild_appstream_dbs()
+}
+
+/// create an app from a component of the search index (for the output of -Ss)
+fn app_from_component(component: &appstream::Component) -> FlatpakApp {
+	let extid = component.flatpak_ref.split_once('/').map(|(_, extid)| extid).unwrap_or(&component.flatpak_ref);
+	let mut parts = extid.splitn(3, '/');
+	let (_, arch, branch) = (parts.next(), parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+	FlatpakApp {
+		name: component.name.clone(),
+		id: component.id.clone(),
+		extid: extid.to_string(),
+		arch: arch.to_string(),
+		branch: branch.to_string(),
+		version: component.version.clone(),
+		description: component.summary.clone(),
+		origin: component.remote.clone(),
+		..Default::default()
+	}
+}
 
-	let mut stdout = String::new();
-	let mut stderr = String::new();
+/// find the installed flatpak package that owns the target  
+/// (the target can be an app id, a command name or a path; symlinks are followed)  
+/// return its index (in flatpak.apps) or None if no app owns it
+fn is_owned_by(flatpak: &mut FlatpakMeta, target: &str) -> Option<usize> {
+	// app ids (or extended app ids)
+	if let Some(index) = flatpak.apps.iter().position(|app| app.id == target || app.extid == target) {
+		return Some(index);
+	}
+	// command names are searched in $PATH (like pacman does), then in the app commands
+	let target_path = if target.contains('/') {
+		PathBuf::from(target)
+	} else {
+		match find_in_path(target) {
+			Some(path) => path,
+			None => return flatpak.search_command(target),
+		}
+	};
+	// symlinks (eg. from the exports dir) are resolved into the active deployment
+	let target_path = fs::canonicalize(&target_path).ok()?;
+	if let Some(index) = flatpak.get_owner_index().lookup(&target_path) {
+		return Some(index);
+	}
+	// exported files that are not symlinks (into a deployment)
+	if target_path.ancestors().any(|dir| dir.ends_with("exports")) {
+		let file_name = target_path.file_name()?.to_string_lossy();
+		return flatpak.search_export(&file_name);
+	}
+	None
+}
 
-	if let Some(mut out) = child.stdout.take() {
-		out.read_to_string(&mut stdout)
-			.expect(&format!("{prefix} failed to read stdout", prefix = text::ERROR_PREFIX.red().bold()));
+/// test if an entry of the files database matches the target  
+/// (a file name, or a path that ends with the path of the entry (with or without `exports/`))
+fn provides_file(entry: &FileEntry, target: &str) -> bool {
+	if !target.contains('/') {
+		return entry.path.rsplit('/').next() == Some(target);
 	}
-	if let Some(mut err) = child.stderr.take() {
-		err.read_to_string(&mut stderr)
-			.expect(&format!("{prefix} failed to read stderr", prefix = text::ERROR_PREFIX.red().bold()));
+	let target = target.trim_start_matches('/');
+	let exported = entry.path.strip_prefix("exports/").unwrap_or(&entry.path);
+	entry.path.ends_with(target) || [entry.path.as_str(), exported].iter()
+		.any(|path| target == *path || target.ends_with(&format!("/{}", path)))
+}
+
+/// output the entries of the files database (grouped by their ref) like `pacman -F`:  
+///     `remote/id/arch/branch (name)`  
+///     `    path`  
+/// (with list: `remote/id/arch/branch path` per line, like `pacman -Fl`)
+fn print_file_entries(entries: &[&FileEntry], list: bool, quiet: bool) {
+	let mut last: Option<&FileEntry> = None;
+	for entry in entries {
+		let name = format!("{}{}{}", entry.remote.magenta().bold(), "/".bold(), entry.extid().bold());
+		if list {
+			if quiet {
+				println!("{}", entry.path);
+			} else {
+				println!("{} {}", name, entry.path);
+			}
+			continue;
+		}
+		if last.is_none_or(|last| last.flatpak_ref != entry.flatpak_ref || last.remote != entry.remote) {
+			if quiet {
+				println!("{}", name);
+			} else {
+				println!("{} ({})", name, entry.name);
+			}
+			last = Some(entry);
+		}
+		if !quiet {
+			println!("{}{}", text::DESCRIPTION_IDENTATION, entry.path);
+		}
 	}
+}
 
-	let status = child.wait()
-		.expect(&format!("{prefix} failed to wait for pacman child process", prefix = text::ERROR_PREFIX.red().bold()));
-	(stdout, stderr, status)
+/// test if an installed flatpak satisfies a dependency  
+/// (a ref like `id`, `id/arch`, `id//branch` or `id/arch/branch`, with an optional version constraint like `id>=1.2`)
+fn deptest_flatpak(flatpak: &mut FlatpakMeta, target: &str) -> bool {
+	let (name, constraint) = version::split_constraint(target);
+	let Some((op, wanted)) = constraint else {
+		return flatpak.search_ref(name).is_some();
+	};
+	flatpak.search_refs(name).into_iter().any(|index| {
+		flatpak.get_app_info(index)
+			.is_ok_and(|app| version::satisfies(&app.version, op, wanted))
+	})
+}
+
+/// search for an executable called `name` in $PATH  
+/// return its full path
+fn find_in_path(name: &str) -> Option<PathBuf> {
+	let paths = env::var_os("PATH")?;
+	env::split_paths(&paths)
+		.map(|dir| dir.join(name))
+		.find(|path| path.is_file())
+}
+
+/// the targets that are installed pacman packages (with one `pacman -Qq` for all targets)  
+/// (root_args: see pacman_root_args())
+fn installed_pacman<'a>(targets: &[&'a str], root_args: &[String]) -> Result<Vec<&'a str>, PacpakError> {
+	if targets.is_empty() {
+		return Ok(Vec::new());
+	}
+	let mut pac_args = root_args.to_vec();
+	pac_args.push("-Qq".to_string());
+	pac_args.extend(targets.iter().map(|target| target.to_string()));
+	// the installed packages are listed (unknown targets are errors on stderr)
+	let (stdout_pac, _, _) = pacman_run(&pac_args)?;
+	let installed: Vec<&str> = stdout_pac.lines().map(str::trim).collect();
+	Ok(targets.iter().filter(|target| installed.contains(target)).copied().collect())
+}
+
+/// the pacman args for the root and database given with -r/--root and -b/--dbpath  
+/// (for the pacman calls pacpak builds itself, args_pacman already contains them)
+fn pacman_root_args(args: &Cli) -> Vec<String> {
+	let mut out = Vec::new();
+	if let Some(root) = &args.install_root {
+		out.extend(["--root".to_string(), root.clone()]);
+	}
+	if let Some(dbpath) = &args.dbpath {
+		out.extend(["--dbpath".to_string(), dbpath.clone()]);
+	}
+	out
+}
+
+/// remove the given targets from a list of (pacman) args
+fn without_targets(args: &[String], targets: &[&str]) -> Vec<String> {
+	args.iter()
+		.filter(|arg| !targets.contains(&arg.as_str()))
+		.cloned()
+		.collect()
+}
+
+/// remove the field selectors of search terms (eg. `name:gimp`) from a list of (pacman) args
+fn without_selectors(args: &[String], targets: &[&str]) -> Vec<String> {
+	args.iter()
+		.map(|arg| if targets.contains(&arg.as_str()) { search::strip_selector(arg).to_string() } else { arg.clone() })
+		.collect()
+}
+
+/// call pacman with the given args (inherit buffers)  
+/// return the exit status
+fn pacman_exec(args: &[String]) -> Result<ExitStatus, PacpakError> {
+	program_exec("pacman", args)
+}
+
+/// call pacman with the given args (pipe buffers)  
+/// return a tuple of (stdout, stderr, exit status)
+fn pacman_run(args: &[String]) -> Result<(String, String, ExitStatus), PacpakError> {
+	program_run("pacman", args)
 }
 
 /// call flatpak with the given args (inherit buffers)  
 /// return the exit status
-fn flatpak_exec(args: &Vec<String>) -> ExitStatus {
-	Command::new("flatpak")
+fn flatpak_exec(args: &[String]) -> Result<ExitStatus, PacpakError> {
+	program_exec("flatpak", args)
+}
+
+/// call flatpak with the given args (pipe buffers)  
+/// return a tuple of (stdout, stderr, exit status)
+fn flatpak_run(args: &[String]) -> Result<(String, String, ExitStatus), PacpakError> {
+	program_run("flatpak", args)
+}
+
+/// call a program with the given args (inherit buffers)  
+/// return the exit status
+fn program_exec(program: &str, args: &[String]) -> Result<ExitStatus, PacpakError> {
+	program_command(program)
 		.args(args)
 		.stdin(Stdio::inherit())
 		.stdout(Stdio::inherit())
 		.stderr(Stdio::inherit())
 		.status()
-		.expect(&format!("{prefix} failed to execute flatpak", prefix = text::ERROR_PREFIX.red().bold()))
+		.map_err(|e| spawn_error(program, e))
 }
 
-/// call flatpak with the given args (pipe buffers)  
+/// call a program with the given args (pipe buffers)  
 /// return a tuple of (stdout, stderr, exit status)
-fn flatpak_run(args: &Vec<String>) -> (String, String, ExitStatus) {
-	let mut child = Command::new("flatpak")
+fn program_run(program: &str, args: &[String]) -> Result<(String, String, ExitStatus), PacpakError> {
+	let output = program_command(program)
 		.args(args)
 		.stdin(Stdio::null())
-		.stdout(Stdio::piped())
-		.stderr(Stdio::piped())
-		.spawn()
-		.expect(&format!("{prefix} failed to execute flatpak", prefix = text::ERROR_PREFIX.red().bold()));
-
-	let mut stdout = String::new();
-	let mut stderr = String::new();
+		.output()
+		.map_err(|e| spawn_error(program, e))?;
+	let stdout = String::from_utf8_lossy(&output.stdout).into();
+	let stderr = String::from_utf8_lossy(&output.stderr).into();
+	Ok((stdout, stderr, output.status))
+}
 
-	if let Some(mut out) = child.stdout.take() {
-		out.read_to_string(&mut stdout)
-			.expect(&format!("{prefix} failed to read stdout", prefix = text::ERROR_PREFIX.red().bold()));
-	}
-	if let Some(mut err) = child.stderr.take() {
-		err.read_to_string(&mut stderr)
-			.expect(&format!("{prefix} failed to read stderr", prefix = text::ERROR_PREFIX.red().bold()));
+/// create the command for a program (flatpak commands use the installation set with -r/--root)
+fn program_command(program: &str) -> Command {
+	if program == "flatpak" {
+		flatpak::flatpak_command()
+	} else {
+		Command::new(program)
 	}
+}
 
-	let status = child.wait()
-		.expect(&format!("{prefix} failed to wait for flatpak child process", prefix = text::ERROR_PREFIX.red().bold()));
-	(stdout, stderr, status)
+/// convert the error of a failed spawn of a program
+fn spawn_error(program: &str, e: io::Error) -> PacpakError {
+	match (program, e.kind()) {
+		("flatpak", io::ErrorKind::NotFound) => PacpakError::FlatpakMissing,
+		("pacman", io::ErrorKind::NotFound) => PacpakError::PacmanMissing,
+		_ => e.into(),
+	}
 }
 
 
 /// entry point
 fn main() {
-	let args = Cli::parse();
-	let args_raw: Vec<String> = env::args().skip(1).collect();
-	let config = Config {	//dev: overwrite some fields for now
-		//color: false,
-		wrap_pacman: true,
-		..Default::default()
-	};
+	match run() {
+		Ok(code) => exit(code),
+		Err(e) => {
+			eprintln!("{}", e);
+			exit(e.exit_status());
+		},
+	}
+}
+
+/// run the operation given by the cli args  
+/// returns the exit status (often the one of pacman or flatpak)
+fn run() -> Result<i32, PacpakError> {
+	let args = Cli::parse(env::args().skip(1))?;
+	let mut config = Config::default();
+	config.load()?;
+	config.ignore_pkg.extend(args.ignore.iter().cloned());
 
-	let mut args_pacman: Vec<String> = args_raw.clone();
+	let mut args_pacman: Vec<String> = args.args_pacman.clone();
+	// for the pacman calls that are not built from args_pacman (eg. to sort the targets)
+	let root_args = pacman_root_args(&args);
 	// add pacman args for color arguments
 	args_pacman.insert(0,"--color".to_string());
 	if config.color {
@@ -266,15 +882,14 @@ fn main() {
 	
 	// pacman integration
 	let mut stderr_pacman = String::new();
-	let status_true: ExitStatus = Command::new("true")
-		.status()
-		.expect(&format!("{prefix} failed to get exit status", prefix = text::ERROR_PREFIX.red().bold()));
-	let mut status: ExitStatus = status_true.clone();
+	// ExitStatus::default() is a success
+	let status_true: ExitStatus = ExitStatus::default();
+	let mut status: ExitStatus = status_true;
 
 	// basic operations
 	if args.help {
 		println!("{}", text::HELP_USAGE);
-		return;
+		return Ok(exit_status::SUCCESS);
 	} else if args.version {
 		let indent = if config.wrap_pacman {
 			text::VERSION_IDENTATION
@@ -287,145 +902,425 @@ fn main() {
 		println!("{}", indent);
 		println!("{}---", indent);
 		println!("{}", indent);
-		let (stdout_flatpak,_,_) = flatpak_run(&vec!["--version".to_string()]);
+		let (stdout_flatpak,_,_) = flatpak_run(&["--version".to_string()])?;
 		println!("{}{}", indent, stdout_flatpak);
 		if config.wrap_pacman {
 			println!("{}---", indent);
-			pacman_exec(&args_pacman);
+			pacman_exec(&args_pacman)?;
 		}
-		return;
+		return Ok(exit_status::SUCCESS);
 	}
 
 	// init flatpak metadata
 	let mut flatpak = FlatpakMeta::default();
+	flatpak.installation = args.installation.clone();
+	// like pacman: -r/--root and -b/--dbpath operate on another system installation (eg. `/mnt/var/lib/flatpak`)
+	if let Some(system_dir) = flatpak::alternate_system_dir(args.install_root.as_deref(), args.dbpath.as_deref()) {
+		flatpak::set_system_dir(system_dir);
+		flatpak.installation.get_or_insert_with(|| "system".to_string());
+	}
 	// update flatpak app list
-	match flatpak.get_apps() {
-		Ok(apps) => apps,
-		Err(e) => {
-			println!("Error: {}", e);
-			exit(exit_status::ERROR);
-		}
-	};
+	flatpak.get_apps()?;
 
 	// the targets for operations (often packages or files)
 	let targets : Vec<&str> = args.targets
 		.iter()
 		.map(|s| s.as_str())
 		.collect();
+	// select flatpaks of an architecture/branch (for targets without them)
+	let (arch, branch) = (args.arch.as_deref(), args.branch.as_deref());
 	
 	// other operations
-	if args.query {
-		if config.wrap_pacman {
+	if args.history {
+		// list the changes of flatpaks (like the pacman log)
+		let mut found = false;
+		// flatpak records downgrades (by pacpak) as updates
+		let downgrades = cache::read_downgrades().unwrap_or_default();
+		for mut entry in flatpak.history()? {
+			// refs, or parts of ids (like the targets of -Q)
+			let id = entry.flatpak_ref.split('/').nth(1).unwrap_or_default().to_lowercase();
+			let matches = |target: &&str| flatpak::ref_matches(&with_branch(target, branch), &entry.flatpak_ref)
+				|| (!target.contains('/') && branch.is_none() && id.contains(&target.to_lowercase()));
+			if !targets.is_empty() && !targets.iter().any(matches) {
+				continue;
+			}
+			found = true;
+			let downgraded = downgrades.iter().any(|d| d.flatpak_ref == entry.flatpak_ref && d.installation == entry.installation
+				&& same_commit(&d.commit, &entry.commit) && same_commit(&d.old_commit, &entry.old_commit));
+			if downgraded {
+				entry.change = entry.change.replace("update", "downgrade");
+			}
+			print_history_entry(&entry);
+		}
+		if !found && !targets.is_empty() {
+			return Ok(exit_status::NOT_FOUND);
+		}
+		return Ok(exit_status::SUCCESS);
+	} else if args.rollback {
+		// update flatpaks to the commit before their last update (--mask: and keep them there)
+		if targets.is_empty() {
+			return Err(PacpakError::NoTargets);
+		}
+		let history = flatpak.history()?;
+		let mut rollbacks: Vec<(usize, String)> = Vec::new();
+		let mut not_found: Vec<PacpakError> = Vec::new();
+		for target in &targets {
+			let Some(&index) = flatpak.select_apps(&[target], arch, branch).first() else {
+				not_found.push(PacpakError::TargetNotFound(target.to_string()));
+				continue;
+			};
+			flatpak.get_kind(index)?;
+			flatpak.get_app_info_full(index)?;
+			let app = &flatpak.apps[index];
+			// the last change that deployed the current commit
+			let previous = history.iter().rev()
+				.filter(|entry| entry.flatpak_ref == app.full_ref() && entry.installation == app.installation)
+				.find(|entry| !entry.old_commit.is_empty() && same_commit(&entry.commit, &app.commit));
+			match previous {
+				Some(entry) => rollbacks.push((index, entry.old_commit.clone())),
+				None => not_found.push(PacpakError::NoPreviousCommit(app.extid.clone())),
+			}
+		}
+		if let Some(last) = not_found.pop() {
+			for e in &not_found {
+				eprintln!("{}", e);
+			}
+			return Err(last);
+		}
+		for (index, commit) in rollbacks {
+			let app = &flatpak.apps[index];
+			println!("{} {} {} ({} => {})", "::".blue().bold(), text::ROLLBACK.bold(), app.extid.bold(), short_commit(&app.commit), short_commit(&commit));
+			let local_status = deploy_commit(app, &commit, args.noconfirm, args.mask)?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+		}
+		return Ok(exit_status::SUCCESS);
+	} else if args.downgrade {
+		// update flatpaks to an older commit from the log of their remote (like `pacman -U` with an old package)
+		if targets.is_empty() {
+			return Err(PacpakError::NoTargets);
+		}
+		if args.commit.as_deref() == Some("") {
+			return Err(PacpakError::Usage(text::EMPTY_COMMIT.to_string()));
+		}
+		// resolve all targets (and their commits) before the first one is downgraded
+		let mut selected: Vec<usize> = Vec::new();
+		let mut not_found: Vec<PacpakError> = Vec::new();
+		for target in &targets {
+			match flatpak.select_apps(&[target], arch, branch).first() {
+				Some(&index) => selected.push(index),
+				None => not_found.push(PacpakError::TargetNotFound(target.to_string())),
+			}
+		}
+		if let Some(last) = not_found.pop() {
+			for e in &not_found {
+				eprintln!("{}", e);
+			}
+			return Err(last);
+		}
+		// the versions of the commits (for --commit VERSION)
+		let history = match args.commit {
+			Some(_) => flatpak.history()?,
+			None => Vec::new(),
+		};
+		let mut downgrades: Vec<(usize, String)> = Vec::new();
+		for index in selected {
+			flatpak.get_kind(index)?;
+			flatpak.get_app_info_full(index)?;
+			let app = &flatpak.apps[index];
+			let commits = flatpak.remote_log(&app.origin, &app.full_ref(), None)?;
+			let installed = commits.iter().position(|commit| same_commit(&commit.commit, &app.commit));
+
+			let commit = match args.commit.as_deref() {
+				// a commit of the log (or a full commit), or a version from the log or the history
+				Some(wanted) => commits.iter()
+					.find(|commit| commit.commit.starts_with(wanted) || commit.version == wanted)
+					.map(|commit| commit.commit.clone())
+					.or_else(|| history.iter()
+						.filter(|entry| entry.flatpak_ref == app.full_ref() && entry.installation == app.installation)
+						.find(|entry| entry.version == wanted && !entry.commit.is_empty())
+						.map(|entry| entry.commit.clone()))
+					.or_else(|| (wanted.len() == 64 && wanted.chars().all(|c| c.is_ascii_hexdigit())).then(|| wanted.to_string()))
+					.ok_or_else(|| PacpakError::Usage(format!("{} {} ({})", text::NO_COMMIT, app.extid, wanted)))?,
+				// choose a commit (the one before the installed one by default)
+				None => {
+					let default = installed.map_or(0, |i| i + 1);
+					if default >= commits.len() {
+						return Err(PacpakError::Usage(format!("{} {}", text::NO_OLDER_COMMIT, app.extid)));
+					}
+					println!("{} {} {} ({}):", "::".blue().bold(), text::COMMITS_OF.bold(), app.full_ref().bold(), app.origin);
+					for (i, commit) in commits.iter().enumerate() {
+						println!("   {}) {}", i + 1, format_log_commit(commit, installed == Some(i)));
+					}
+					println!();
+					let number = if args.noconfirm {
+						default + 1
+					} else {
+						match choose_number(commits.len(), default + 1) {
+							Some(number) => number,
+							None => return Ok(exit_status::FAILURE),
+						}
+					};
+					commits[number - 1].commit.clone()
+				},
+			};
+			if same_commit(&commit, &app.commit) {
+				println!("{} {}: {}", text::WARNING_PREFIX.cyan().bold(), app.extid, text::ALREADY_DEPLOYED);
+				continue;
+			}
+			downgrades.push((index, commit));
+		}// for index
+		for (index, commit) in downgrades {
+			let app = &flatpak.apps[index];
+			println!("{} {} {} ({} => {})", "::".blue().bold(), text::DOWNGRADE.bold(), app.extid.bold(), short_commit(&app.commit), short_commit(&commit));
+			let local_status = deploy_commit(app, &commit, args.noconfirm, args.mask)?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+		}
+		return Ok(exit_status::SUCCESS);
+	} else if args.query {
+		// merge the lists of -Q and -Qs (instead of pacman first)
+		let merged = args.merged && args.info == 0 && !args.list && !args.owns;
+		let mut entries: Vec<(String, String)> = Vec::new();
+		let args_pacman = if args.search { without_selectors(&args_pacman, &targets) } else { args_pacman };
+		if config.wrap_pacman && merged {
+			// uncolored (so it can be parsed)
+			let mut args_merged = args_pacman.clone();
+			if let Some(i) = args_merged.iter().position(|arg| arg == "--color") {
+				args_merged[i + 1] = "never".to_string();
+			}
+			let (stdout_pac, stderr_pac, status_pac) = pacman_run(&args_merged)?;
+			entries = pacman_entries(&stdout_pac);
+			stderr_pacman = stderr_pac;
+			status = status_pac;
+		}
+		// -Qo calls pacman only for the targets that no flatpak owns
+		if config.wrap_pacman && !args.owns && !merged {
 			let mut cmd = Command::new("pacman");
 			cmd.args(&args_pacman)
 				.stdin(Stdio::inherit())
 				.stdout(Stdio::inherit());
 			cmd.stderr(Stdio::piped());
 			let mut child = cmd.spawn()
-				.expect(&format!("{prefix} failed to execute pacman", prefix = text::ERROR_PREFIX.red().bold()));
+				.map_err(|e| spawn_error("pacman", e))?;
 			if let Some(mut err) = child.stderr.take() {
-				err.read_to_string(&mut stderr_pacman)
-					.expect(&format!("{prefix} failed to read stderr", prefix = text::ERROR_PREFIX.red().bold()));
+				err.read_to_string(&mut stderr_pacman)?;
 			}
-			status = child.wait()
-				.expect(&format!("{prefix} failed to wait on pacman", prefix = text::ERROR_PREFIX.red().bold()));
+			status = child.wait()?;
 		}
-		if args.info {
+		if args.info > 0 {
 			// show info for a package
-			let results = flatpak.search_apps(&targets);
+			let results = flatpak.select_apps(&targets, arch, branch);
 			if results.is_empty() && status.code().unwrap_or(exit_status::ERROR) > 0 {
 				eprintln!("{}", stderr_pacman);
-				exit(status.code().unwrap_or(exit_status::ERROR));
+				let installed = if config.wrap_pacman { installed_pacman(&targets, &root_args)? } else { Vec::new() };
+				let unknown: Vec<&str> = targets.iter().filter(|target| !installed.contains(target)).copied().collect();
+				print_suggestions(&unknown, &suggestion_names(&flatpak, false, config.wrap_pacman, &root_args));
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
 			} else if results.is_empty() {
-				exit(status.code().unwrap_or(exit_status::ERROR));
-			}
-            for index in results {
-                match flatpak.get_app_info_full(index) {
-                    Ok(app) => app,
-                    Err(e) => {
-                        eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), e);
-                        exit(exit_status::ERROR);
-                    }
-                };
-                print_app_info(&flatpak.apps[index]);
-            }
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
+			}
+			for index in results {
+				flatpak.get_app_info_full(index)?;
+				flatpak.get_pinned(index)?;
+				print_app_info(&flatpak.apps[index]);
+			}
 		} else if args.owns {
 			// which package owns this file
-			if targets.len() == 0 {
-				eprintln!("{}", stderr_pacman);
-				exit(status.code().unwrap_or(exit_status::ERROR));
-				//eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), "no targets specified");	//dev
-				//exit(1);
-			}
-			// look if already found (by pacman)
-			if status.code().unwrap_or(exit_status::ERROR) == 0 {
-				exit(status.code().unwrap_or(exit_status::ERROR));
+			if targets.is_empty() && !config.wrap_pacman {
+				return Err(PacpakError::NoTargets);
 			}
+			let mut owned: Vec<&str> = Vec::new();
 			for target in &targets {
-				let idx = is_owned_by(&mut flatpak, target);
-				let idx = match idx {
-					Ok(idx) => idx,
-                    Err(e) => {
-                        eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), e);
-                        exit(exit_status::ERROR);
-                    }
+				let Some(index) = is_owned_by(&mut flatpak, target) else {
+					continue;
 				};
-				if idx >= 0 {
-					println!("{}", flatpak.apps[idx as usize].extid);
-				} else {
-					eprintln!("{}", stderr_pacman);
-					exit(status.code().unwrap_or(exit_status::ERROR));
-					//eprintln!("Error : {} {}", "no package owns", target);	//dev
-				}
+				let _ = flatpak.get_app_info(index);
+				let app = &flatpak.apps[index];
+				println!("{} is owned by {} {}", target, app.extid.bold(), app.version.green().bold());
+				owned.push(target);
 			}//for target
+			
+			// the remaining targets are handled by pacman
+			let not_owned: Vec<&str> = targets.iter()
+				.filter(|target| !owned.contains(target))
+				.copied()
+				.collect();
+			if not_owned.is_empty() && !targets.is_empty() {
+				return Ok(exit_status::SUCCESS);
+			}
+			if config.wrap_pacman {
+				let local_status = pacman_exec(&without_targets(&args_pacman, &owned))?;
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+			for target in &not_owned {
+				eprintln!("{} {} {}", text::ERROR_PREFIX.red().bold(), text::NO_OWNER, target);
+			}
+			return Ok(exit_status::NOT_FOUND);
 		} else if args.list {
 			// list files of a package
-			let matches: Vec<usize> = flatpak.search_apps(&targets);
-			if matches.len() == 0 {
+			let mut matches: Vec<usize> = flatpak.select_apps(&targets, arch, branch);
+			if matches.is_empty() {
 				eprintln!("{}", stderr_pacman);
-				exit(status.code().unwrap_or(exit_status::ERROR));
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
+			}
+			if args.related {
+				for i in matches.clone() {
+					matches.extend(flatpak.get_related(i));
+				}
+				matches.sort();
+				matches.dedup();
 			}
 			for i in matches {
-				let files = match flatpak.get_app_files(i) {
-					Ok(f) => f,
-					Err(e) => {
-                        eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), e);
-                        exit(exit_status::ERROR);
-					}
-				};
+				let files = flatpak.get_app_files(i, args.exports)?;
 				for f in &files {
-					println!("{} {}", flatpak.apps[i].extid.bold(), f);
+					if args.quiet {
+						println!("{}", f);
+					} else {
+						println!("{} {}", flatpak.apps[i].extid.bold(), f);
+					}
 				}
 			}
 		} else if args.search {
-			let matches: Vec<usize>  = flatpak.search_apps_desc(&targets);
-			if matches.len() == 0 {
+			let matcher = Matcher::new(&targets)?;
+			let mut matches: Vec<usize> = flatpak.search_apps_desc(&matcher);
+			if let Some(kind) = &args.kind {
+				matches.retain(|i| flatpak.get_kind(*i).is_ok_and(|app| &app.kind == kind));
+			}
+			if let Some(remote) = &args.remote {
+				matches.retain(|i| &flatpak.apps[*i].origin == remote);
+			}
+			if matches.is_empty() && entries.is_empty() {
 				//eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), "no package found");	//dev
-				exit(exit_status::NOT_FOUND);
+				return Ok(exit_status::NOT_FOUND);
 			}
 			for i in matches {
 				let app = &flatpak.apps[i];
 				let installed = false;
-				print_app_long(&app, installed);
+				if merged {
+					entries.push((merged_key(app), format_app_long(app, installed)));
+				} else {
+					print_app_long(app, installed);
+				}
+			}
+			if merged {
+				eprint!("{}", stderr_pacman);
+				print_merged(entries);
 			}
 			
+		} else if args.check > 0 {
+			// check the files of flatpaks (-Qkk: also the objects in the repo of their installation)
+			let results = flatpak.select_apps(&targets, arch, branch);
+			if results.is_empty() && !targets.is_empty() {
+				eprint!("{}", stderr_pacman);
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
+			}
+			// the problems pacman found (without its errors about the flatpak targets)
+			let found: Vec<&str> = targets.iter()
+				.filter(|target| !flatpak.select_apps(&[target], arch, branch).is_empty())
+				.copied()
+				.collect();
+			eprint!("{}", without_not_found(&stderr_pacman, &found));
+			let mut repairs: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+			let mut found_problems = false;
+			for index in results {
+				flatpak.get_kind(index)?;
+				let check = flatpak.check_files(index)?;
+				let app = &flatpak.apps[index];
+				for (path, reason) in &check.missing {
+					eprintln!("{} {}: {} ({})", text::WARNING_PREFIX.cyan().bold(), app.extid, path.display(), reason);
+				}
+				let mut altered: Vec<String> = Vec::new();
+				if args.check > 1 {
+					// like pacman: only -Qkk checks the properties of the files
+					altered.extend(check.altered.iter().map(|(path, reason)| format!("{} ({})", path.display(), reason)));
+					if !repairs.contains_key(&app.installation) {
+						repairs.insert(app.installation.clone(), flatpak::repair_dry_run(&app.installation)?);
+					}
+					altered.extend(repairs[&app.installation].get(&app.full_ref()).cloned().unwrap_or_default());
+					for problem in &altered {
+						eprintln!("{} {}: {}", text::WARNING_PREFIX.cyan().bold(), app.extid, problem);
+					}
+					println!("{}: {} total files, {} altered files", app.extid, check.total, check.missing.len() + altered.len());
+				} else {
+					println!("{}: {} total files, {} missing files", app.extid, check.total, check.missing.len());
+				}
+				found_problems |= !check.missing.is_empty() || !altered.is_empty();
+			}
+			if found_problems {
+				return Ok(exit_status::FAILURE);
+			}
+			return Ok(status.code().unwrap_or(exit_status::ERROR));
+		} else if args.sysupgrade > 0 {
+			// list the flatpaks that have an update (-Quc: and what changed)
+			let selected = flatpak.select_apps(&targets, arch, branch);
+			let updates: Vec<(usize, String)> = flatpak.get_updates()?.into_iter()
+				.filter(|(index, _)| selected.contains(index))
+				.collect();
+			if updates.is_empty() {
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
+			}
+			let mut catalogs = appstream::Catalogs::default();
+			for (index, new_version) in updates {
+				flatpak.get_app_info(index)?;
+				flatpak.get_kind(index)?;
+				let app = &flatpak.apps[index];
+				if args.quiet {
+					println!("{}", app.extid.bold());
+				} else {
+					let ignored = if config.is_ignored(&app.full_ref()) { format!(" {}", text::IGNORED_MARKER) } else { String::new() };
+					println!("{} {} -> {}{}", app.extid.bold(), app.version.green().bold(), new_version.green().bold(), ignored);
+				}
+				if args.clean > 0 {
+					print_changelog(&mut flatpak, &mut catalogs, index, Some(&new_version), args.offline)?;
+				}
+			}
+			return Ok(exit_status::SUCCESS);
+		} else if args.clean > 0 {
+			// show the changelog of flatpaks
+			let results = flatpak.select_apps(&targets, arch, branch);
+			if results.is_empty() || targets.is_empty() {
+				eprint!("{}", stderr_pacman);
+				if targets.is_empty() && !config.wrap_pacman {
+					return Err(PacpakError::NoTargets);
+				}
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
+			}
+			let mut catalogs = appstream::Catalogs::default();
+			let mut found = true;
+			for index in results {
+				found &= print_changelog(&mut flatpak, &mut catalogs, index, None, args.offline)?;
+			}
+			if !found {
+				return Ok(exit_status::FAILURE);
+			}
 		} else {
 			// just -Q
-			let results = flatpak.search_apps(&targets);
-			if results.is_empty() {
+			let results = flatpak.select_apps(&targets, arch, branch);
+			if results.is_empty() && entries.is_empty() {
 				eprintln!("{}", stderr_pacman);
-				exit(status.code().unwrap_or(exit_status::ERROR));
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
 			}
 			for index in results {
-                match flatpak.get_app_info(index) {
-                    Ok(app) => app,
-                    Err(e) => {
-                        eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), e);
-                        exit(exit_status::ERROR);
-                    }
-                };
+                flatpak.get_app_info(index)?;
                 let app : &FlatpakApp = &flatpak.apps[index];
-				print_app_short(app);
+				if merged {
+					entries.push((merged_key(app), format_app_merged(app, args.quiet)));
+				} else if args.quiet {
+					println!("{}", app.extid.bold());
+				} else {
+					print_app_short(app);
+				}
+			}
+			if merged {
+				let found: Vec<&str> = targets.iter()
+					.filter(|target| !flatpak.select_apps(&[target], arch, branch).is_empty())
+					.copied()
+					.collect();
+				eprint!("{}", without_not_found(&stderr_pacman, &found));
+				print_merged(entries);
 			}
 		}
 	
@@ -458,92 +1353,399 @@ fn main() {
 		//	//dev uninstall flatpaks
 		//	println!("flatpak uninstall: {:?}", pkgs_flat);
 		//}
+		// -Sy also refreshes the search index (with or without targets)
+		if args.refresh > 0 {
+			println!("{} {}", "::".blue().bold(), text::SYNC_SEARCH.bold());
+			refresh_appstream(&flatpak, args.noconfirm)?;
+		}
 		if args.search {
 			// format: remote/print_app_short() [installed]
-			pacman_exec(&args_pacman);
+			let matcher = Matcher::new(&targets)?;
+			pacman_exec(&without_selectors(&args_pacman, &targets))?;
 			
-			// giving flatpak search an emtpy target shows all possible packages
-			let flat_targets = if targets.len() > 0 {targets} else {vec![""]};
-			let matches: Vec<FlatpakApp>  = match flatpak.search(flat_targets) {
-				Ok(app) => app,
-				Err(e) => {
-					eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), e);
-					exit(exit_status::ERROR);
-				}
+			// search the local index (built from the AppStream catalogs, if it is missing)
+			let mut index = cache::read_search()?;
+			if index.is_none() && !appstream::catalog_paths().is_empty() {
+				rebuild_appstream_dbs()?;
+				index = cache::read_search()?;
+			}
+			let mut matches: Vec<FlatpakApp> = match index.filter(|components| !components.is_empty()) {
+				Some(components) => {
+					let components: Vec<&appstream::Component> = components.iter()
+						.filter(|c| args.kind.as_ref().is_none_or(|kind| c.flatpak_ref.starts_with(&format!("{}/", kind))))
+						.collect();
+					matcher.rank(components.iter().copied()).into_iter()
+						.map(|i| app_from_component(components[i]))
+						.collect()
+				},
+				None if args.offline => {
+					eprintln!("{} {}", text::WARNING_PREFIX.cyan().bold(), text::NO_SEARCH_DB);
+					Vec::new()
+				},
+				// flatpak search gets one plain term (an emtpy one shows all possible packages), the results are matched like the index
+				None => {
+					let found = flatpak.search(vec![search::plain_term(&targets).unwrap_or_default()])?;
+					matcher.rank(&found).into_iter().map(|i| found[i].clone()).collect()
+				},
 			};
-			if matches.len() == 0 {
+			if let Some(remote) = &args.remote {
+				matches.retain(|app| app.origin.split(',').any(|origin| origin == remote));
+			}
+			if args.installed {
+				matches.retain(|app| flatpak.is_installed(app));
+			}
+			if matches.is_empty() {
 				//eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), "no package found");
-				exit(exit_status::NOT_FOUND);
+				return Ok(exit_status::NOT_FOUND);
 			}
 			for app in &matches {
-				let installed = flatpak.apps.iter()
-					.any(|a| a.id == app.id && a.branch == app.branch);
-				print_app_long(&app, installed);
+				print_app_long(app, flatpak.is_installed(app));
 			}
 			//search
+		} else if args.list {
+			// list the packages of the repos/remotes
+			let remotes = flatpak.get_remotes()?;
+			let mut remotes_flat: Vec<&str> = targets.iter()
+				.copied()
+				.filter(|target| remotes.iter().any(|remote| remote == target))
+				.collect();
+			if let Some(remote) = &args.remote {
+				remotes_flat.push(remote);
+			}
+			if (targets.is_empty() && args.remote.is_none()) || targets.len() > remotes_flat.len() {
+				status = pacman_exec(&without_targets(&args_pacman, &remotes_flat))?;
+			}
+			if targets.is_empty() && args.remote.is_none() {
+				remotes_flat = remotes.iter().map(String::as_str).collect();
+			}
+			let mut failed: Option<i32> = None;
+			for remote in remotes_flat {
+				// the list is cached, so it can be shown offline
+				let listed = if args.offline {
+					Err(PacpakError::Usage(text::NO_CACHED_LIST.to_string()))
+				} else {
+					flatpak.remote_list(remote)
+				};
+				let apps = match listed {
+					Ok(apps) => {
+						cache::write_remote_list(remote, &apps)?;
+						apps
+					},
+					Err(e) => match cache::read_remote_list(remote)? {
+						Some(apps) => {
+							if !args.offline {
+								println!("{} {}: {}", text::WARNING_PREFIX.cyan().bold(), remote, text::USING_CACHED_LIST);
+							}
+							apps
+						},
+						None => {
+							eprintln!("{}", e);
+							failed = Some(e.exit_status());
+							continue;
+						},
+					},
+				};
+				for app in &apps {
+					if args.quiet {
+						println!("{}", app.id);
+					} else {
+						print_app_long(app, flatpak.is_installed(app));
+					}
+				}
+			}
+			return Ok(failed.unwrap_or(status.code().unwrap_or(exit_status::ERROR)));
+		} else if args.info > 0 {
+			// show info for packages (pacman first, then flatpaks from the remotes)
+			let mut pkgs_flat: Vec<&str> = Vec::new();
+			for pkg in &targets {
+				let (_, _, status_pac) = pacman_run(&[root_args.clone(), vec!["-Si".to_string(), pkg.to_string()]].concat())?;
+				if !status_pac.success() {
+					pkgs_flat.push(pkg);
+				}
+			}
+			if targets.is_empty() || targets.len() > pkgs_flat.len() {
+				status = pacman_exec(&without_targets(&args_pacman, &pkgs_flat))?;
+			}
+			let mut not_found: Vec<PacpakError> = Vec::new();
+			for pkg in pkgs_flat {
+				let pkg = with_branch(pkg, branch);
+				let id = pkg.split('/').next().unwrap_or_default();
+				let remotes = flatpak_remotes(&flatpak, id, args.remote.as_ref())?;
+				match remotes.first().map(|remote| flatpak.remote_info(remote, &pkg, arch)) {
+					Some(Ok(app)) => print_app_sync_info(&app),
+					_ => not_found.push(PacpakError::TargetNotFound(pkg)),
+				}
+			}
+			if let Some(last) = not_found.pop() {
+				for e in &not_found {
+					eprintln!("{}", e);
+				}
+				return Err(last);
+			}
+			return Ok(status.code().unwrap_or(exit_status::ERROR));
+		} else if targets.is_empty() && args.sysupgrade > 0 {
+			// upgrade all packages (the ignored flatpaks are left out)
+			let local_status = pacman_exec(&args_pacman)?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+			let mut flat_args = vec!["update".to_string()];
+			if args.noconfirm {
+				flat_args.push(flatpak_strings::NONINTERACTIVE.to_string());
+			}
+			if config.ignore_pkg.is_empty() && config.ignore_related.is_empty() {
+				flat_args.extend(flatpak.scope_args());
+				let local_status = flatpak_exec(&flat_args)?;
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+			// only update the refs that are not ignored
+			let mut refs: Vec<(String, String)> = Vec::new();
+			for (index, new_version) in flatpak.get_updates()? {
+				flatpak.get_kind(index)?;
+				let _ = flatpak.get_app_info(index);
+				let app = &flatpak.apps[index];
+				if config.is_ignored(&app.full_ref()) {
+					println!("{} {}: {} ({} => {})", text::WARNING_PREFIX.cyan().bold(), app.id, text::IGNORING_UPGRADE, app.version, new_version);
+				} else {
+					refs.push((app.installation_arg(), app.full_ref()));
+				}
+			}
+			if refs.is_empty() {
+				println!(" {}", text::NOTHING_TO_DO);
+				return Ok(exit_status::SUCCESS);
+			}
+			// the refs of each installation are updated together
+			refs.sort();
+			let mut installations: Vec<&String> = refs.iter().map(|(arg, _)| arg).collect();
+			installations.dedup();
+			for installation in installations {
+				let mut update_args = flat_args.clone();
+				update_args.push(installation.clone());
+				update_args.extend(refs.iter().filter(|(arg, _)| arg == installation).map(|(_, r)| r.clone()));
+				let local_status = flatpak_exec(&update_args)?;
+				if !local_status.success() {
+					return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+				}
+			}
+			return Ok(exit_status::SUCCESS);
+		} else if targets.is_empty() && args.clean > 0 {
+			// clean the package cache of pacman, then remove unused flatpaks (-Scc: also prune the repos)
+			let local_status = pacman_exec(&args_pacman)?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+			// like pacman: --noconfirm takes the default answers
+			let ask = |question: &str, default: bool| if args.noconfirm { default } else { confirm(question, default) };
+			println!();
+			println!("{}", text::CLEAN_KEEP);
+			println!("{}", text::CLEAN_KEEP_ALL);
+			for (installation, path) in flatpak_installations(&flatpak) {
+				println!();
+				println!("{} {}", text::CLEAN_INSTALLATION, path.display());
+				// the removed data is in the repo (deployments are hard links to its objects)
+				let repo = path.join(flatpak_strings::REPO_DIR);
+				let size_before = flatpak::disk_usage(&repo);
+				if ask(text::CLEAN_UNUSED, true) {
+					println!("{}", text::CLEANING_UNUSED);
+					let flat_args = ["uninstall", "--unused", flatpak_strings::ASSUME_YES, &flatpak::installation_arg(&installation)]
+						.map(String::from);
+					let local_status = flatpak_exec(&flat_args)?;
+					if !local_status.success() {
+						return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+					}
+				}
+				if args.clean > 1 && ask(text::CLEAN_REPO, false) {
+					println!("{}", text::CLEANING_REPO);
+					if let Err(e) = flatpak::remove_repo_tmp(&path) {
+						eprintln!("{}", e);
+					}
+					// remove the objects that no ref uses (the history of the refs is kept)
+					let ostree_args = ["prune".to_string(), format!("--repo={}", repo.display()), "--refs-only".to_string()];
+					let local_status = program_exec("ostree", &ostree_args)?;
+					if !local_status.success() {
+						return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+					}
+				}
+				let reclaimed = size_before.saturating_sub(flatpak::disk_usage(&repo));
+				println!("{} {}", text::CLEAN_RECLAIMED.bold(), format_size(reclaimed));
+			}// for installation
+			return Ok(exit_status::SUCCESS);
+		} else if targets.is_empty() && args.refresh > 0 {
+			// -Sy without targets (the search index is refreshed above)
+			let local_status = pacman_exec(&args_pacman)?;
+			return Ok(local_status.code().unwrap_or(exit_status::ERROR));
 		} else {
-			if targets.len() == 0 {
-				eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), text::NO_TARGETS);
-				exit(exit_status::NOT_FOUND);
+			if targets.is_empty() {
+				return Err(PacpakError::NoTargets);
 			}
+			// sort the targets (prefer pacman packages)
+			let mut pkgs_pac: Vec<&str> = Vec::new();
+			let mut pkgs_flat: Vec<&str> = Vec::new();
 			for pkg in &targets {
 				let pkg_pac: String = format!("^{}$", pkg);
-				let (stdout_pac, _stderr_pac, status_pac) = pacman_run(&vec!["-Ss".to_string(), pkg_pac]);
+				let (stdout_pac, _stderr_pac, status_pac) = pacman_run(&[root_args.clone(), vec!["-Ss".to_string(), pkg_pac]].concat())?;
 				if !stdout_pac.is_empty() && status_pac == status_true {
-					// install with pacman
-					let local_status = pacman_exec(&args_pacman);
-					if !local_status.success() {
-						exit(local_status.code().unwrap_or(exit_status::ERROR));
-					}
+					pkgs_pac.push(pkg);
 				} else {
-					println!("{} {}: {}", text::WARNING_PREFIX.cyan().bold(), text::NO_PACMAN_PACKAGE, pkg.to_string());
-					//else try flatpak
-					//TODO: test if a package does not exist and wrap the error
-					//let (stdout_flat, stderr_flat, status_flat) = flatpak_run(&vec!["-Ss".to_string(), pkg.to_string()]);
-					let local_status = flatpak_exec(&vec!["install".to_string(), pkg.to_string()]);
-					if !local_status.success() {
-						exit(local_status.code().unwrap_or(exit_status::ERROR));
+					println!("{} {}: {}", text::WARNING_PREFIX.cyan().bold(), text::NO_PACMAN_PACKAGE, pkg);
+					pkgs_flat.push(pkg);
+				}
+			}
+			if !pkgs_pac.is_empty() || args.refresh > 0 || args.sysupgrade > 0 {
+				// install with pacman (it handles --needed/--noconfirm itself)
+				let local_status = pacman_exec(&without_targets(&args_pacman, &pkgs_flat))?;
+				if !local_status.success() {
+					return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+				}
+			}
+			// with --cachedir: install from the sideload repo in it (eg. created by `flatpak create-usb`)
+			// or from the bundles in it (eg. exported by `pacpak -Sw --cachedir`)
+			let sideload = args.cachedir.as_deref()
+				.filter(|_| !args.downloadonly)
+				.and_then(bundle::sideload_repo);
+			let bundles = match &args.cachedir {
+				Some(dir) if !args.downloadonly && sideload.is_none() => bundle::read_bundle_dir(dir)?,
+				_ => Vec::new(),
+			};
+			let mut installed_bundles: Vec<&str> = Vec::new();
+			let mut downloaded: Vec<String> = Vec::new();
+			// flatpak installs the default arch (if none is given)
+			let default_arch = flatpak::default_arch();
+			let install_arch = arch.or(Some(default_arch.as_str()));
+			for pkg in pkgs_flat {
+				// only the same id with the same arch/branch is up to date (names are not searched)
+				let mut parts = pkg.splitn(3, '/');
+				let (id, pkg_arch, pkg_branch) = (
+					parts.next().unwrap_or_default(),
+					parts.next().filter(|part| !part.is_empty()).or(install_arch).unwrap_or_default(),
+					parts.next().filter(|part| !part.is_empty()).or(branch).unwrap_or_default(),
+				);
+				if args.needed && let Some(index) = flatpak.search_ref(&format!("{}/{}/{}", id, pkg_arch, pkg_branch)) {
+					println!("{} {} {}", text::WARNING_PREFIX.cyan().bold(), flatpak.apps[index].extid, text::UP_TO_DATE);
+					continue;
+				}
+				let pkg = &with_branch(pkg, branch);
+				// like pacman: ask before installing ignored packages
+				let pkg_ref = if pkg.contains('/') { pkg.to_string() } else { format!("{}//", pkg) };
+				if config.is_ignored(&pkg_ref) {
+					let question = format!("{} {}", pkg, text::IGNORED_INSTALL);
+					if !args.noconfirm && !confirm(&question, true) {
+						continue;
+					}
+				}
+				let pattern = format!("{}/{}/{}", id, pkg_arch, pkg_branch);
+				if let Some(local) = bundles.iter().find(|local| flatpak::ref_matches(&pattern, &local.flatpak_ref)) {
+					// the runtime of the app first (if it is bundled and not installed yet)
+					let runtime = bundles.iter()
+						.filter(|_| !local.runtime.is_empty() && flatpak.search_ref(&local.runtime).is_none())
+						.find(|other| other.is_runtime() && flatpak::ref_matches(&local.runtime, &other.flatpak_ref));
+					for local in runtime.into_iter().chain([local]) {
+						if installed_bundles.contains(&local.flatpak_ref.as_str()) {
+							continue;
+						}
+						let mut flat_args = vec!["install".to_string()];
+						if args.noconfirm {
+							flat_args.push(flatpak_strings::NONINTERACTIVE.to_string());
+						}
+						flat_args.extend(flatpak.scope_args());
+						flat_args.extend(local_install_args(local));
+						let local_status = flatpak_exec(&flat_args)?;
+						if !local_status.success() {
+							return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+						}
+						installed_bundles.push(&local.flatpak_ref);
 					}
+					continue;
 				}
+				//TODO: test if a package does not exist and wrap the error
+				let mut flat_args = vec!["install".to_string()];
+				if args.noconfirm {
+					flat_args.push(flatpak_strings::NONINTERACTIVE.to_string());
+				}
+				flat_args.extend(flatpak.scope_args());
+				flat_args.extend(arch.map(|arch| format!("--arch={}", arch)));
+				if args.downloadonly {
+					flat_args.push("--no-deploy".to_string());
+				}
+				flat_args.extend(sideload.iter().map(|repo| format!("--sideload-repo={}", repo.display())));
+				flat_args.push(pkg.to_string());
+				let local_status = flatpak_exec(&flat_args)?;
+				if !local_status.success() {
+					print_suggestions(&[pkg], &suggestion_names(&flatpak, true, config.wrap_pacman, &root_args));
+					return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+				}
+				downloaded.push(pkg.to_string());
+			}
+			if args.downloadonly && let Some(dir) = &args.cachedir && !downloaded.is_empty() {
+				let local_status = export_flatpaks(&flatpak, &downloaded, dir, args.remote.as_ref(), arch)?;
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
 			}
-			exit(exit_status::SUCCESS);
+			return Ok(exit_status::SUCCESS);
 		}
 	} else if args.remove {
-		let mut pkgs_pac: Vec<String> = Vec::new();
+		let mut pkgs_pac: Vec<&str> = Vec::new();
 		let mut pkgs_flat: Vec<FlatpakApp> = Vec::new();
+		let mut not_found: Vec<&str> = Vec::new();
 		
-		if targets.len() == 0 {
-			eprintln!("{} {}", text::ERROR_PREFIX.red().bold(), text::NO_TARGETS);
-			exit(exit_status::NOT_FOUND);
+		if targets.is_empty() {
+			return Err(PacpakError::NoTargets);
 		}
 
+		let installed = installed_pacman(&targets, &root_args)?;
 		for pkg in targets.clone() {
-			let (_, _, status_pac) = pacman_run(&vec!["-Q".to_string(), pkg.to_string()]);
-			//dev: match for flatpaks better
-			let found_flat = flatpak.search_apps(&vec![pkg]);
-			if status_pac == status_true && found_flat.len() > 0 {
-				pkgs_pac.push(pkg.to_string());
-				pkgs_flat.push(flatpak.apps[found_flat[0]].clone());
-			} else if status_pac == status_true {
-				pkgs_pac.push(pkg.to_string());
-			} else if found_flat.len() > 0 {
-				pkgs_flat.push(flatpak.apps[found_flat[0]].clone());
-			} else {
-				//dev error
-				println!("{} no fitting package found '{}'", text::ERROR_PREFIX.red().bold(), pkg);
+			let is_pacman = installed.contains(&pkg);
+			let found_flat = flatpak.select_apps(&[pkg], arch, branch);
+			// a plain name has to match one flatpak, a ref (or --arch/--branch) removes every match
+			if !pkg.contains('/') && arch.is_none() && branch.is_none() && found_flat.len() > 1 {
+				let candidates = found_flat.iter()
+					.map(|i| format!("{} [{}]", flatpak.apps[*i].extid, flatpak.apps[*i].installation))
+					.collect();
+				return Err(PacpakError::AmbiguousTarget(pkg.to_string(), candidates));
+			}
+			if is_pacman {
+				pkgs_pac.push(pkg);
+			}
+			pkgs_flat.extend(found_flat.iter().map(|i| flatpak.apps[*i].clone()));
+			if !is_pacman && found_flat.is_empty() {
+				not_found.push(pkg);
+			}
+		}
+		// like pacman: remove nothing if a target is unknown
+		if let Some((last, others)) = not_found.split_last() {
+			print_suggestions(&not_found, &suggestion_names(&flatpak, false, config.wrap_pacman, &root_args));
+			for pkg in others {
+				eprintln!("{}", PacpakError::TargetNotFound(pkg.to_string()));
+			}
+			return Err(PacpakError::TargetNotFound(last.to_string()));
+		}
+
+		// like pacman: ask before removing held packages
+		let mut held = false;
+		for app in pkgs_flat.iter_mut() {
+			let index = flatpak.apps.iter().position(|a| a.extid == app.extid && a.installation == app.installation);
+			if let Some(index) = index {
+				flatpak.get_kind(index)?;
+				app.kind = flatpak.apps[index].kind.clone();
+			}
+			if config.is_held(&app.full_ref()) {
+				println!("{} {} {}", text::WARNING_PREFIX.cyan().bold(), app.id, text::HOLD_PKG);
+				held = true;
 			}
 		}
+		if held && (args.noconfirm || !confirm(text::HOLD_PKG_CONTINUE, false)) {
+			return Ok(exit_status::FAILURE);
+		}
 
 		let pkgs_pac_len = pkgs_pac.len();
-		if pkgs_pac.len() > 0 {
-			let mut pac_args = vec!["-Rs".to_string()];	//detect more uninstall options
-			pac_args.append(&mut pkgs_pac);
-			let local_status = pacman_exec(&pac_args);
+		if !pkgs_pac.is_empty() {
+			// the options of the removal (eg. -s, -n, -c, --dbonly) are passed on to pacman
+			let only_flat: Vec<&str> = targets.iter().filter(|pkg| !pkgs_pac.contains(pkg)).copied().collect();
+			let local_status = pacman_exec(&without_targets(&args_pacman, &only_flat))?;
 			if !local_status.success() {
-				exit(local_status.code().unwrap_or(exit_status::ERROR));
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
 			}
 		}
-		if pkgs_flat.len() > 0 {
+		if !pkgs_flat.is_empty() {
 			let mut flat_del_pkg:String = String::new();
 			for app in &pkgs_flat {
 				let extra_string = format!("{}/{}", app.arch, app.branch);
@@ -551,34 +1753,216 @@ fn main() {
 				flat_del_pkg = format!("{}{}  ", flat_del_pkg, custom_extid);
 			}
 
-			println!("");
+			println!();
 			if pkgs_pac_len > 0 { println!("{}", text::UNINSTALL_SPACER.bold()); }
 			println!("{} {}", "Removing:".bold(), flat_del_pkg);
 			
 			for app in &pkgs_flat {
-				let local_status = flatpak_exec(&vec!["uninstall".to_string(), app.extid.to_string()]);
+				let mut flat_args = vec!["uninstall".to_string()];
+				if args.noconfirm {
+					flat_args.push(flatpak_strings::NONINTERACTIVE.to_string());
+				}
+				flat_args.push(app.installation_arg());
+				flat_args.push(app.extid.to_string());
+				let local_status = flatpak_exec(&flat_args)?;
 				if !local_status.success() {
-					exit(local_status.code().unwrap_or(exit_status::ERROR));
+					return Ok(local_status.code().unwrap_or(exit_status::ERROR));
 				}
 			}
 		}
-		exit(exit_status::SUCCESS);
-	} else if args.database {
-		pacman_exec(&args_pacman);
-		println!("Operation not implemented.");
-	} else if args.deptest {
-		pacman_exec(&args_pacman);
-		println!("Operation not implemented.");
+		return Ok(exit_status::SUCCESS);
 	} else if args.upgrade {
-		pacman_exec(&args_pacman);
+		// sort the files (by their extension and content)
+		let mut files_flat: Vec<LocalFlatpak> = Vec::new();
+		for target in &targets {
+			if let Some(refs) = bundle::read_sideload_repo(target)? {
+				files_flat.extend(refs);
+			} else if let Some(local) = bundle::read_local(target)? {
+				files_flat.push(local);
+			}
+		}
+		// the runtimes first (without a network, the apps can not get them from a remote)
+		files_flat.sort_by_key(|local| !local.is_runtime());
+		let paths_flat: Vec<&str> = files_flat.iter().map(|f| f.path.as_str()).collect();
+		if targets.is_empty() || targets.len() > files_flat.len() {
+			let local_status = pacman_exec(&without_targets(&args_pacman, &paths_flat))?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+		}
+		if files_flat.is_empty() {
+			return Ok(exit_status::SUCCESS);
+		}
+
+		println!();
+		print_local_plan(&files_flat);
+		if !args.noconfirm && !confirm(text::PROCEED_INSTALL, true) {
+			return Ok(exit_status::FAILURE);
+		}
+		for local in &files_flat {
+			// already confirmed above
+			let mut flat_args = vec![
+				"install".to_string(),
+				flatpak_strings::NONINTERACTIVE.to_string(),
+			];
+			flat_args.extend(flatpak.scope_args());
+			flat_args.extend(local_install_args(local));
+			let local_status = flatpak_exec(&flat_args)?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+		}
+		return Ok(exit_status::SUCCESS);
+	} else if args.deptest {
+		// pacman prints the targets it cannot satisfy (one per line)
+		let missing_pac: Vec<String> = if config.wrap_pacman {
+			let mut args_deptest = args_pacman.clone();
+			args_deptest[1] = "never".to_string();
+			let (stdout_pac, stderr_pac, status_pac) = pacman_run(&args_deptest)?;
+			eprint!("{}", stderr_pac);
+			if !status_pac.success() && status_pac.code() != Some(exit_status::MISSING) {
+				return Ok(status_pac.code().unwrap_or(exit_status::ERROR));
+			}
+			stdout_pac.lines().map(String::from).collect()
+		} else {
+			args.targets.clone()
+		};
+		let mut missing = false;
+		for target in &missing_pac {
+			if !deptest_flatpak(&mut flatpak, target) {
+				println!("{}", target);
+				missing = true;
+			}
+		}
+		if missing {
+			return Ok(exit_status::MISSING);
+		}
+		return Ok(exit_status::SUCCESS);
+	} else if args.database && (args.asdeps || args.asexplicit) {
+		// flatpak runtimes are explicitly installed if they are pinned
+		if targets.is_empty() {
+			return Err(PacpakError::NoTargets);
+		}
+		let mut pkgs_flat: Vec<(&str, Vec<usize>)> = Vec::new();
+		let mut not_found: Vec<PacpakError> = Vec::new();
+		let installed = installed_pacman(&targets, &root_args)?;
+		for pkg in targets.iter().filter(|pkg| !installed.contains(pkg)) {
+			let found_flat = flatpak.search_refs(pkg);
+			if found_flat.is_empty() {
+				not_found.push(PacpakError::TargetNotFound(pkg.to_string()));
+			} else {
+				pkgs_flat.push((pkg, found_flat));
+			}
+		}
+		if let Some(last) = not_found.pop() {
+			for e in &not_found {
+				eprintln!("{}", e);
+			}
+			return Err(last);
+		}
+		let flat_targets: Vec<&str> = pkgs_flat.iter().map(|(pkg, _)| *pkg).collect();
+		if targets.len() > flat_targets.len() {
+			let local_status = pacman_exec(&without_targets(&args_pacman, &flat_targets))?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+		}
+		for index in pkgs_flat.into_iter().flat_map(|(_, found)| found) {
+			flatpak.get_kind(index)?;
+			let app = &flatpak.apps[index];
+			if app.kind != "runtime" {
+				if args.asdeps {
+					println!("{} {}: {}", text::WARNING_PREFIX.cyan().bold(), app.extid, text::APP_ALWAYS_EXPLICIT);
+				} else {
+					println!("{}: {}", app.extid, text::REASON_SET_EXP);
+				}
+				continue;
+			}
+			let mut flat_args = vec!["pin".to_string(), app.installation_arg()];
+			if args.asdeps {
+				flat_args.push("--remove".to_string());
+			}
+			flat_args.push(app.full_ref());
+			let local_status = flatpak_exec(&flat_args)?;
+			if !local_status.success() {
+				return Ok(local_status.code().unwrap_or(exit_status::ERROR));
+			}
+			let reason = if args.asdeps { text::REASON_SET_DEP } else { text::REASON_SET_EXP };
+			println!("{}: {}", app.extid, reason);
+		}
+		return Ok(exit_status::SUCCESS);
+	} else if args.database && args.check > 0 {
+		// check the flatpak installations (missing runtimes and the objects in their repos)
+		if config.wrap_pacman {
+			status = pacman_exec(&args_pacman)?;
+		}
+		let mut found_problems = false;
+		for index in 0..flatpak.apps.len() {
+			flatpak.get_kind(index)?;
+			if flatpak.apps[index].kind != "app" {
+				continue;
+			}
+			flatpak.get_app_info_full(index)?;
+			let app = &flatpak.apps[index];
+			// the runtime is given as `id/arch/branch`
+			if !app.runtime.is_empty() && !flatpak.apps.iter().any(|other| other.extid == app.runtime) {
+				eprintln!("{} missing '{}' dependency for '{}'", text::ERROR_PREFIX.red().bold(), app.runtime, app.extid);
+				found_problems = true;
+			}
+		}
+		for (installation, _) in flatpak_installations(&flatpak) {
+			let mut repairs: Vec<(String, Vec<String>)> = flatpak::repair_dry_run(&installation)?.into_iter().collect();
+			repairs.sort();
+			for (flatpak_ref, problems) in repairs {
+				let name = if flatpak_ref.is_empty() { &installation } else { &flatpak_ref };
+				for problem in problems {
+					eprintln!("{} {}: {}", text::ERROR_PREFIX.red().bold(), name, problem);
+					found_problems = true;
+				}
+			}
+		}
+		if found_problems {
+			return Ok(exit_status::FAILURE);
+		}
+		println!("{}", text::NO_FLATPAK_ERRORS);
+		return Ok(status.code().unwrap_or(exit_status::ERROR));
+	} else if args.database {
+		pacman_exec(&args_pacman)?;
 		println!("Operation not implemented.");
 	} else if args.files {
-		pacman_exec(&args_pacman);
-		//let _ = flatpak.get_dependencies(0);
-		//println!("{}", flatpak.apps[0].depends);
-		println!("Operation not implemented.");
+		let mut found = false;
+		if config.wrap_pacman {
+			status = pacman_exec(&args_pacman)?;
+		}
+		if args.refresh > 0 {
+			println!("{} {}", "::".blue().bold(), text::SYNC_FILES.bold());
+			refresh_appstream(&flatpak, args.noconfirm)?;
+		}
+		if !targets.is_empty() {
+			let Some(entries) = cache::read_files()? else {
+				eprintln!("{} {}", text::WARNING_PREFIX.cyan().bold(), text::NO_FILES_DB);
+				return Ok(status.code().unwrap_or(exit_status::ERROR));
+			};
+			for target in &targets {
+				let matches: Vec<&FileEntry> = if args.list {
+					entries.iter()
+						.filter(|e| e.extid() == *target || e.extid().starts_with(&format!("{}/", target)))
+						.collect()
+				} else {
+					entries.iter().filter(|e| provides_file(e, target)).collect()
+				};
+				found |= !matches.is_empty();
+				print_file_entries(&matches, args.list, args.quiet);
+			}
+		}
+		if found {
+			return Ok(exit_status::SUCCESS);
+		}
+		return Ok(status.code().unwrap_or(exit_status::ERROR));
 	}
 
 	//println!("{}", "Hello, world!".blue());
 	//println!("pattern: {:?}, path: {:?}", args.pattern, args.path)
+
+	Ok(exit_status::SUCCESS)
 }
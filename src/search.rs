@@ -0,0 +1,293 @@
+//! matching of search terms (for -Qs, -Ss and the targets of other operations)
+// search.rs
+
+use std::cmp::Reverse;
+use regex::{Regex, RegexBuilder};
+
+use crate::appstream::Component;
+use crate::error::PacpakError;
+use crate::flatpak::FlatpakApp;
+
+/// the fields of a package that can be searched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+	Id,
+	Name,
+	Description,
+	Remote,
+}
+
+impl Field {
+	/// all fields (searched by terms without a field selector)
+	pub const ALL: [Field; 4] = [Field::Id, Field::Name, Field::Description, Field::Remote];
+
+	/// the field of a selector (eg. `name` in `name:gimp`)
+	fn from_selector(selector: &str) -> Option<Field> {
+		match selector {
+			"id" => Some(Field::Id),
+			"name" => Some(Field::Name),
+			"description" | "desc" => Some(Field::Description),
+			"remote" => Some(Field::Remote),
+			_ => None,
+		}
+	}
+}
+
+/// something that can be searched (eg. an installed app or a component of an AppStream catalog)
+pub trait Searchable {
+	/// the values of a field (empty values are skipped)
+	fn values(&self, field: Field) -> Vec<&str>;
+}
+
+impl Searchable for FlatpakApp {
+	fn values(&self, field: Field) -> Vec<&str> {
+		match field {
+			Field::Id => vec![&self.id, &self.extid],
+			Field::Name => vec![&self.name],
+			Field::Description => vec![&self.description],
+			Field::Remote => self.origin.split(',').collect(),
+		}
+	}
+}
+
+impl Searchable for Component {
+	fn values(&self, field: Field) -> Vec<&str> {
+		match field {
+			Field::Id => vec![&self.id, &self.flatpak_ref],
+			Field::Name => vec![&self.name],
+			Field::Description => {
+				let mut values: Vec<&str> = vec![&self.summary, &self.developer];
+				values.extend(self.keywords.iter().chain(&self.categories).map(String::as_str));
+				values
+			},
+			Field::Remote => vec![&self.remote],
+		}
+	}
+}
+
+/// search terms (like the ones of `pacman -Ss`: case insensitive regexes, that all have to match)
+/// a term can be limited to a field with a selector (eg. `name:^gimp`, `remote:flathub`)
+#[derive(Debug, Default)]
+pub struct Matcher {
+	terms: Vec<(Vec<Field>, Regex)>,
+}
+
+impl Matcher {
+	/// parse terms as regexes (with optional field selectors)
+	pub fn new(terms: &[&str]) -> Result<Self, PacpakError> {
+		let mut out = Vec::new();
+		for term in terms.iter().filter(|term| !term.is_empty()) {
+			let (fields, pattern) = split_selector(term);
+			let regex = RegexBuilder::new(pattern)
+				.case_insensitive(true)
+				.build()
+				.map_err(|_| PacpakError::Usage(format!("invalid regular expression '{}'", pattern)))?;
+			out.push((fields, regex));
+		}
+		Ok(Self { terms: out })
+	}
+
+	/// match terms literally (as substrings) in the given fields (eg. for the targets of -Q)
+	pub fn literal(terms: &[&str], fields: &[Field]) -> Self {
+		let terms = terms.iter()
+			.filter(|term| !term.is_empty())
+			.filter_map(|term| RegexBuilder::new(&regex::escape(term)).case_insensitive(true).build().ok())
+			.map(|regex| (fields.to_vec(), regex))
+			.collect();
+		Self { terms }
+	}
+
+	/// how well all terms match (higher is better)
+	/// (whole ids before whole names, partial names, partial ids, descriptions and remotes)
+	/// returns None if a term does not match
+	pub fn score(&self, item: &impl Searchable) -> Option<u32> {
+		self.terms.iter()
+			.map(|(fields, regex)| fields.iter()
+				.filter_map(|field| field_score(item, *field, regex))
+				.max())
+			.try_fold(0, |sum, score| score.map(|score| sum + score))
+	}
+
+	/// filter items by the terms
+	/// returns the indexes of the matching items, the most relevant first (equally relevant ones keep their order)
+	pub fn rank<'a, T: Searchable + 'a>(&self, items: impl IntoIterator<Item = &'a T>) -> Vec<usize> {
+		let mut out: Vec<(u32, usize)> = items.into_iter()
+			.enumerate()
+			.filter_map(|(i, item)| self.score(item).map(|score| (score, i)))
+			.collect();
+		out.sort_by_key(|(score, _)| Reverse(*score));
+		out.into_iter().map(|(_, i)| i).collect()
+	}
+}
+
+/// split the field selector off a term (terms without one search all fields)
+fn split_selector(term: &str) -> (Vec<Field>, &str) {
+	match term.split_once(':') {
+		Some((selector, pattern)) if let Some(field) = Field::from_selector(selector) => (vec![field], pattern),
+		_ => (Field::ALL.to_vec(), term),
+	}
+}
+
+/// the pattern of a term without its field selector (eg. `^gimp` for `name:^gimp`, for pacman)
+pub fn strip_selector(term: &str) -> &str {
+	split_selector(term).1
+}
+
+/// the longest term that `flatpak search` can search for (it takes one literal text)  
+/// (terms with regex syntax and terms for remotes are only matched by the Matcher)
+pub fn plain_term<'a>(terms: &[&'a str]) -> Option<&'a str> {
+	terms.iter()
+		.map(|term| split_selector(term))
+		.filter(|(fields, pattern)| {
+			!pattern.is_empty() && fields.as_slice() != [Field::Remote]
+				&& pattern.chars().all(|c| c.is_alphanumeric() || "._- ".contains(c))
+		})
+		.map(|(_, pattern)| pattern)
+		.max_by_key(|pattern| pattern.len())
+}
+
+/// how well a regex matches a field of an item
+/// returns None if it does not match
+fn field_score(item: &impl Searchable, field: Field, regex: &Regex) -> Option<u32> {
+	let values = item.values(field);
+	let whole = |value: &str| regex.find(value).is_some_and(|m| m.start() == 0 && m.end() == value.len());
+	let score = match field {
+		// the last part of an id is matched as a whole too (eg. `gimp` for `org.gimp.GIMP`)
+		Field::Id if values.iter().any(|id| whole(id) || id.rsplit('.').next().is_some_and(&whole)) => 100,
+		Field::Name if values.iter().any(|name| whole(name)) => 80,
+		Field::Name => 40,
+		Field::Id => 30,
+		Field::Description => 10,
+		Field::Remote => 1,
+	};
+	values.iter()
+		.any(|value| !value.is_empty() && regex.is_match(value))
+		.then_some(score)
+}
+
+/// find the names that are closest to an unknown target (by their edit distance, for `did you mean`)
+/// (dotted ids are also compared by their last part, eg. `gimp` for `org.gimp.GIMP`)
+/// returns up to 3 names, the closest first
+pub fn suggest<'a>(target: &str, names: &'a [String]) -> Vec<&'a str> {
+	let target = target.split('/').next().unwrap_or_default().to_lowercase();
+	let target_len = target.chars().count();
+	let max_distance = (target_len / 3).max(1);
+	// the edit distance is at least the difference of the lengths (so most names are skipped without computing it)
+	let close = |other: &str| target_len.abs_diff(other.chars().count()) <= max_distance;
+	let mut out: Vec<(usize, &str)> = names.iter()
+		.filter_map(|name| {
+			let lower = name.to_lowercase();
+			let last = lower.rsplit('.').next().unwrap_or_default();
+			let distance = [lower.as_str(), last].into_iter()
+				.filter(|other| close(other))
+				.map(|other| edit_distance(&target, other))
+				.min()?;
+			(distance <= max_distance && lower != target).then_some((distance, name.as_str()))
+		})
+		.collect();
+	out.sort();
+	out.dedup_by(|a, b| a.1 == b.1);
+	out.into_iter().take(3).map(|(_, name)| name).collect()
+}
+
+/// the edit distance of two strings (in chars, a swap of two neighbouring chars counts as one edit)
+fn edit_distance(a: &str, b: &str) -> usize {
+	let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+	let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+	for (i, row) in d.iter_mut().enumerate() {
+		row[0] = i;
+	}
+	d[0] = (0..=b.len()).collect();
+	for i in 1..=a.len() {
+		for j in 1..=b.len() {
+			let cost = usize::from(a[i - 1] != b[j - 1]);
+			d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+			if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+				d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+			}
+		}
+	}
+	d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn app(id: &str, name: &str, description: &str, origin: &str) -> FlatpakApp {
+		FlatpakApp {
+			id: id.to_string(),
+			extid: format!("{}/x86_64/stable", id),
+			name: name.to_string(),
+			description: description.to_string(),
+			origin: origin.to_string(),
+			..Default::default()
+		}
+	}
+
+	fn apps() -> Vec<FlatpakApp> {
+		vec![
+			app("org.gnome.Photos", "Photos", "Access, organize and share your photos (like GIMP)", "flathub"),
+			app("org.gimp.GIMP", "GNU Image Manipulation Program", "Create images and edit photographs", "flathub"),
+			app("org.example.Gimpish", "Gimpish", "Not GIMP", "fedora"),
+		]
+	}
+
+	#[test]
+	fn ranks_ids_before_names_and_descriptions() {
+		let apps = apps();
+		assert_eq!(Matcher::new(&["gimp"]).unwrap().rank(&apps), [1, 2, 0]);
+		assert_eq!(Matcher::new(&["GIMP", "photo"]).unwrap().rank(&apps), [1, 0]);
+		assert_eq!(Matcher::new(&["^org\\.gimp"]).unwrap().rank(&apps), [1]);
+		assert!(Matcher::new(&["nothing"]).unwrap().rank(&apps).is_empty());
+		// no terms match everything
+		assert_eq!(Matcher::new(&[]).unwrap().rank(&apps), [0, 1, 2]);
+		assert!(Matcher::new(&["("]).is_err());
+	}
+
+	#[test]
+	fn limits_terms_to_selected_fields() {
+		let apps = apps();
+		assert_eq!(Matcher::new(&["name:gimp"]).unwrap().rank(&apps), [2]);
+		assert_eq!(Matcher::new(&["remote:fedora"]).unwrap().rank(&apps), [2]);
+		assert_eq!(Matcher::new(&["desc:photo"]).unwrap().rank(&apps), [0, 1]);
+		// unknown selectors are part of the pattern
+		assert!(Matcher::new(&["foo:gimp"]).unwrap().rank(&apps).is_empty());
+		assert_eq!(Matcher::literal(&["gimp"], &[Field::Id]).rank(&apps), [1, 2]);
+		assert_eq!(Matcher::literal(&["org.gimp"], &[Field::Id, Field::Name]).rank(&apps), [1]);
+	}
+
+	#[test]
+	fn measures_edit_distances() {
+		assert_eq!(edit_distance("", ""), 0);
+		assert_eq!(edit_distance("vlc", ""), 3);
+		assert_eq!(edit_distance("vlc", "vlc"), 0);
+		assert_eq!(edit_distance("vlx", "vlc"), 1);
+		assert_eq!(edit_distance("vcl", "vlc"), 1);
+		assert_eq!(edit_distance("kitten", "sitting"), 3);
+		assert_eq!(edit_distance("gímp", "gimp"), 1);
+	}
+
+	#[test]
+	fn suggests_close_names() {
+		let names: Vec<String> = ["vlc", "org.videolan.VLC", "org.gimp.GIMP", "firefox", "gimp-help"]
+			.map(String::from).to_vec();
+		assert_eq!(suggest("vlx", &names), ["org.videolan.VLC", "vlc"]);
+		assert_eq!(suggest("gmip", &names), ["org.gimp.GIMP"]);
+		assert_eq!(suggest("firefox/x86_64", &names), Vec::<&str>::new());
+		assert_eq!(suggest("firefx", &names), ["firefox"]);
+		assert!(suggest("libreoffice", &names).is_empty());
+	}
+
+	#[test]
+	fn strips_selectors() {
+		assert_eq!(strip_selector("name:^gimp"), "^gimp");
+		assert_eq!(strip_selector("desc:a:b"), "a:b");
+		assert_eq!(strip_selector("foo:bar"), "foo:bar");
+		assert_eq!(strip_selector("gimp"), "gimp");
+		assert_eq!(plain_term(&["name:gimp", "image"]), Some("image"));
+		assert_eq!(plain_term(&["^gimp", "remote:flathub", "org.gimp"]), Some("org.gimp"));
+		assert_eq!(plain_term(&["^gimp$", "remote:flathub"]), None);
+		assert_eq!(plain_term(&[]), None);
+	}
+}
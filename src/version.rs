@@ -0,0 +1,225 @@
+//! compare versions like pacman (`vercmp`)
+// version.rs
+
+use std::cmp::Ordering;
+
+/// the operators of version constraints (longest first)
+const OPERATORS: [&str; 5] = [">=", "<=", "=", "<", ">"];
+
+/// split a target with a version constraint (eg. `foo>=1.2`)
+/// returns tuples of (name, Some((operator, version)))
+pub fn split_constraint(target: &str) -> (&str, Option<(&str, &str)>) {
+	let Some(start) = target.find(['<', '>', '=']) else {
+		return (target, None);
+	};
+	let rest = &target[start..];
+	let op = OPERATORS.iter()
+		.find(|op| rest.starts_with(**op))
+		.copied()
+		.unwrap_or("=");
+	(&target[..start], Some((op, &rest[op.len()..])))
+}
+
+/// test if a version satisfies a constraint (eg. `>=`, `1.2`)
+pub fn satisfies(version: &str, op: &str, wanted: &str) -> bool {
+	let order = vercmp(version, wanted);
+	match op {
+		">=" => order != Ordering::Less,
+		"<=" => order != Ordering::Greater,
+		"<" => order == Ordering::Less,
+		">" => order == Ordering::Greater,
+		_ => order == Ordering::Equal,
+	}
+}
+
+/// compare two versions (of the form `[epoch:]version[-release]`) like pacman
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+	let (epoch_a, version_a, release_a) = split_evr(a);
+	let (epoch_b, version_b, release_b) = split_evr(b);
+	rpmvercmp(epoch_a, epoch_b)
+		.then_with(|| rpmvercmp(version_a, version_b))
+		.then_with(|| match (release_a, release_b) {
+			(Some(release_a), Some(release_b)) => rpmvercmp(release_a, release_b),
+			_ => Ordering::Equal,
+		})
+}
+
+/// split a version into (epoch, version, release)
+fn split_evr(full: &str) -> (&str, &str, Option<&str>) {
+	let (epoch, rest) = match full.split_once(':') {
+		Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => (epoch, rest),
+		_ => ("0", full),
+	};
+	let epoch = if epoch.is_empty() { "0" } else { epoch };
+	match rest.rsplit_once('-') {
+		Some((version, release)) => (epoch, version, Some(release)),
+		None => (epoch, rest, None),
+	}
+}
+
+/// compare version strings segment by segment (numeric and alphabetic segments)
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+	if a == b {
+		return Ordering::Equal;
+	}
+	let (a, b) = (a.as_bytes(), b.as_bytes());
+	let (mut i, mut j) = (0, 0);
+	while i < a.len() && j < b.len() {
+		// skip the separators
+		let (sep_i, sep_j) = (i, j);
+		while i < a.len() && !a[i].is_ascii_alphanumeric() { i += 1; }
+		while j < b.len() && !b[j].is_ascii_alphanumeric() { j += 1; }
+		if i >= a.len() || j >= b.len() {
+			break;
+		}
+		if i - sep_i != j - sep_j {
+			return (i - sep_i).cmp(&(j - sep_j));
+		}
+
+		// the next segment (of the same kind in both versions)
+		let (start_i, start_j) = (i, j);
+		let is_num = a[i].is_ascii_digit();
+		let same_kind = |c: &u8| if is_num { c.is_ascii_digit() } else { c.is_ascii_alphabetic() };
+		while i < a.len() && same_kind(&a[i]) { i += 1; }
+		while j < b.len() && same_kind(&b[j]) { j += 1; }
+		let (seg_a, seg_b) = (&a[start_i..i], &b[start_j..j]);
+		if seg_b.is_empty() {
+			// numeric segments are newer than alphabetic ones
+			return if is_num { Ordering::Greater } else { Ordering::Less };
+		}
+
+		let order = if is_num {
+			let trim = |seg: &[u8]| seg.iter().position(|c| *c != b'0').map_or(0, |p| seg.len() - p);
+			let (len_a, len_b) = (trim(seg_a), trim(seg_b));
+			len_a.cmp(&len_b).then_with(|| seg_a[seg_a.len() - len_a..].cmp(&seg_b[seg_b.len() - len_b..]))
+		} else {
+			seg_a.cmp(seg_b)
+		};
+		if order != Ordering::Equal {
+			return order;
+		}
+	}// while
+
+	let (rest_a, rest_b) = (&a[i.min(a.len())..], &b[j.min(b.len())..]);
+	if rest_a.is_empty() && rest_b.is_empty() {
+		return Ordering::Equal;
+	}
+	// the version with the remaining segment is newer (unless it is alphabetic, eg. `1.0alpha`)
+	let alpha_a = rest_a.first().is_some_and(|c| c.is_ascii_alphabetic());
+	let alpha_b = rest_b.first().is_some_and(|c| c.is_ascii_alphabetic());
+	if (rest_a.is_empty() && !alpha_b) || alpha_a {
+		Ordering::Less
+	} else {
+		Ordering::Greater
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// the test table of pacman's `vercmp` (each pair is also tested in reverse)
+	const VERCMP_TABLE: [(&str, &str, i8); 52] = [
+		// all similar length, no pkgrel
+		("1.5.0", "1.5.0", 0),
+		("1.5.1", "1.5.0", 1),
+		// mixed length
+		("1.5.1", "1.5", 1),
+		// with pkgrel, simple
+		("1.5.0-1", "1.5.0-1", 0),
+		("1.5.0-1", "1.5.0-2", -1),
+		("1.5.0-1", "1.5.1-1", -1),
+		("1.5.0-2", "1.5.1-1", -1),
+		// with pkgrel, mixed lengths
+		("1.5-1", "1.5.1-1", -1),
+		("1.5-2", "1.5.1-1", -1),
+		("1.5-2", "1.5.1-2", -1),
+		// mixed pkgrel inclusion
+		("1.5", "1.5-1", 0),
+		("1.5-1", "1.5", 0),
+		("1.1-1", "1.1", 0),
+		("1.0-1", "1.1", -1),
+		("1.1-1", "1.0", 1),
+		// alphanumeric versions
+		("1.5b-1", "1.5-1", -1),
+		("1.5b", "1.5", -1),
+		("1.5b-1", "1.5", -1),
+		("1.5b", "1.5.1", -1),
+		// from the manpage
+		("1.0a", "1.0alpha", -1),
+		("1.0alpha", "1.0b", -1),
+		("1.0b", "1.0beta", -1),
+		("1.0beta", "1.0rc", -1),
+		("1.0rc", "1.0", -1),
+		// alpha-dotted versions
+		("1.5.a", "1.5", 1),
+		("1.5.b", "1.5.a", 1),
+		("1.5.1", "1.5.b", 1),
+		// alpha dots and dashes
+		("1.5.b-1", "1.5.b", 0),
+		("1.5-1", "1.5.b", -1),
+		// same/similar content, differing separators
+		("2.0", "2_0", 0),
+		("2.0_a", "2_0.a", 0),
+		("2.0a", "2.0.a", -1),
+		("2___a", "2_a", 1),
+		// epoch included version comparisons
+		("0:1.0", "0:1.0", 0),
+		("0:1.0", "0:1.1", -1),
+		("1:1.0", "0:1.0", 1),
+		("1:1.0", "0:1.1", 1),
+		("1:1.0", "2:1.1", -1),
+		// epoch + sometimes present pkgrel
+		("1:1.0", "0:1.0-1", 1),
+		("1:1.0-1", "0:1.1-1", 1),
+		// epoch included on one version
+		("0:1.0", "1.0", 0),
+		("0:1.1", "1.0", 1),
+		("0:1.1", "1.1", 0),
+		("1:1.0", "1.0", 1),
+		("1:1.0", "1.1", 1),
+		("1:1.1", "1.1", 1),
+		// leading zeros and longer numbers
+		("1.01", "1.1", 0),
+		("1.10", "1.9", 1),
+		("1.001.0", "1.1", 1),
+		("20240101", "9", 1),
+		// flatpak versions without release
+		("2.10.38", "2.10.40", -1),
+		("3.0.21", "3.0.20", 1),
+	];
+
+	fn order(expected: i8) -> Ordering {
+		expected.cmp(&0)
+	}
+
+	#[test]
+	fn compares_like_pacman() {
+		for (a, b, expected) in VERCMP_TABLE {
+			assert_eq!(vercmp(a, b), order(expected), "vercmp {} {}", a, b);
+			assert_eq!(vercmp(b, a), order(-expected), "vercmp {} {}", b, a);
+		}
+	}
+
+	#[test]
+	fn compares_segments() {
+		assert_eq!(rpmvercmp("1.0", "1.0"), Ordering::Equal);
+		assert_eq!(rpmvercmp("a", "1"), Ordering::Less);
+		assert_eq!(rpmvercmp("1", "a"), Ordering::Greater);
+		assert_eq!(rpmvercmp("", "1"), Ordering::Less);
+		assert_eq!(rpmvercmp("1.", "1"), Ordering::Greater);
+	}
+
+	#[test]
+	fn checks_constraints() {
+		assert_eq!(split_constraint("foo>=1.2"), ("foo", Some((">=", "1.2"))));
+		assert_eq!(split_constraint("foo<2"), ("foo", Some(("<", "2"))));
+		assert_eq!(split_constraint("foo=1:1.0-2"), ("foo", Some(("=", "1:1.0-2"))));
+		assert_eq!(split_constraint("foo"), ("foo", None));
+		assert!(satisfies("1.2", ">=", "1.2"));
+		assert!(satisfies("1.3", ">", "1.2"));
+		assert!(!satisfies("1.2", "<", "1.2"));
+		assert!(satisfies("1.2-3", "=", "1.2"));
+		assert!(satisfies("1:0.1", ">", "2.0"));
+	}
+}